@@ -0,0 +1,413 @@
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+
+use crate::{
+    config::Config,
+    confirmation::Confirmation,
+    events::ExplorerEvent,
+    explorer::Explorer,
+    finder::Finder,
+    grep::{Grep, GrepEvent},
+    input::Input,
+    jobs::{JobStatus, Jobs},
+    paste::{PasteJob, PasteStatus, PendingPaste},
+    preview::Preview,
+    sh::{expand, PreparedSh},
+    trash::{TrashItem, TrashView},
+    viewer::Viewer,
+};
+
+// How many lines a page up or down scrolls the expanded output.
+const OUTPUT_PAGE: i32 = 10;
+
+/// Everything that lives alongside the explorer: prompts, background tasks, and the log.
+pub struct App {
+    pub input: Input,
+    pub confirmation: Confirmation,
+    pub finder: Finder,
+    pub grep: Grep,
+    pub trash: TrashView,
+    pub jobs: Jobs,
+    pub preview: Preview,
+    pub viewer: Viewer,
+    // Whether the shown job's output is expanded into a popup.
+    pub output_expanded: bool,
+    // An interactive command, waiting for the window to be suspended so it can run.
+    pub suspended: Option<PreparedSh>,
+
+    pub paste: Option<PendingPaste>,
+    pub paste_job: Option<PasteJob>,
+
+    // A string for a previous log.
+    pub log_string: String,
+}
+
+impl App {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            input: Input::new(),
+            confirmation: Confirmation::new(),
+            finder: Finder::new(),
+            grep: Grep::new(config),
+            trash: TrashView::new(),
+            jobs: Jobs::new(config),
+            preview: Preview::new(config),
+            viewer: Viewer::new(config),
+            output_expanded: false,
+            suspended: None,
+            paste: None,
+            paste_job: None,
+            log_string: String::new(),
+        }
+    }
+
+    /// Polls background tasks, writing their progress to the log.
+    pub fn update(&mut self, explorer: &mut Explorer) -> anyhow::Result<()> {
+        // Collect any paths the fuzzy finder's walker has found.
+        self.finder.update();
+        self.grep.update();
+        self.viewer.update();
+
+        // Journal whatever finished jobs did, and mention them if their output is hidden.
+        // Cancelled jobs were already mentioned when they were cancelled.
+        let shown = self.jobs.shown().map(|x| x.id);
+        for job in self.jobs.update() {
+            let cancelled = matches!(job.status, JobStatus::Cancelled);
+            if !cancelled && (shown != Some(job.id) || !self.log_string.is_empty()) {
+                let outcome = match job.succeeded() {
+                    true => "finished",
+                    false => "failed",
+                };
+                self.log_string = format!(
+                    "Job {}: {} {outcome} ({})",
+                    job.id,
+                    job.command,
+                    job.describe_status()
+                );
+            }
+
+            let Some(snapshot) = job.take_snapshot() else {
+                continue;
+            };
+            match snapshot.changes(explorer.journal_mut()) {
+                Ok(moves) => explorer.record(format!("Run {}", job.command), moves),
+                Err(e) => self.log_string = format!("Error: {e}"),
+            }
+        }
+
+        if let Some(status) = self.paste_job.as_ref().and_then(|x| x.poll()) {
+            match status {
+                PasteStatus::Progress { done, total, path } => {
+                    self.log_string = format!("Pasting {done}/{total}: {}", path.display());
+                    return Ok(());
+                }
+                PasteStatus::Finished => {
+                    let job = self.paste_job.take().expect("Paste job should be Some");
+                    if job.cut {
+                        explorer.clear_register();
+                    }
+                    let pasted = job.pasted();
+                    self.log_file_op("Pasted", Ok(pasted.clone()));
+
+                    // The files are already pasted, so nothing here stops the rest from happening.
+                    let res = job
+                        .moves(pasted.len(), false, explorer.journal_mut())
+                        .map(|x| explorer.record(format!("Paste {} entries", pasted.len()), x));
+                    let res = res
+                        .and(explorer.refresh())
+                        .and_then(|_| match pasted.first() {
+                            Some(path) => explorer.reveal(path),
+                            None => Ok(()),
+                        });
+                    if let Err(e) = res {
+                        self.log_string = format!("Error: {e}");
+                    }
+                }
+                PasteStatus::Failed(e, completed, trashed) => {
+                    let job = self.paste_job.take().expect("Paste job should be Some");
+                    self.log_string = format!("Error: {e}");
+
+                    let res = job
+                        .moves(completed, trashed, explorer.journal_mut())
+                        .map(|x| explorer.record(format!("Paste {completed} entries"), x));
+                    if let Err(e) = res.and(explorer.refresh()) {
+                        self.log_string = format!("Error: {e}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts pasting the register into the focused directory, asking about conflicts first.
+    fn paste(&mut self, explorer: &mut Explorer) {
+        if self.paste_job.is_some() {
+            self.log_string = "Error: a paste is already running".to_string();
+            return;
+        }
+        let Some(register) = explorer.register() else {
+            self.log_string = "Error: nothing to paste".to_string();
+            return;
+        };
+
+        let pending = match PendingPaste::new(register, &explorer.focused_dir()) {
+            Ok(pending) => pending,
+            Err(e) => {
+                self.log_string = format!("Error: {e}");
+                return;
+            }
+        };
+        match pending.conflict() {
+            Some(_) => self.paste = Some(pending),
+            None => self.start_paste(pending, explorer),
+        }
+    }
+
+    /// Starts a paste once every conflict has been resolved.
+    pub fn start_paste(&mut self, pending: PendingPaste, explorer: &mut Explorer) {
+        match pending.start(explorer.journal_mut()) {
+            Ok(job) => self.paste_job = Some(job),
+            Err(e) => self.log_string = format!("Error: {e}"),
+        }
+    }
+
+    /// Runs an event triggered by a binding, a double click, an input, or a confirmation.
+    /// Returns true if the program should quit.
+    pub fn run_event(
+        &mut self,
+        event: ExplorerEvent,
+        explorer: &mut Explorer,
+        text: Option<String>,
+    ) -> anyhow::Result<bool> {
+        match event {
+            ExplorerEvent::Quit => return Ok(true),
+            ExplorerEvent::Sh {
+                command,
+                args,
+                each,
+                interactive,
+            } => {
+                let sh = PreparedSh::new(explorer, &command, &args, each, text);
+                self.run_sh(sh, interactive);
+            }
+            ExplorerEvent::Shell {
+                command,
+                each,
+                interactive,
+            } => {
+                let sh = PreparedSh::shell(explorer, &command, each, text);
+                self.run_sh(sh, interactive);
+            }
+            ExplorerEvent::Jobs => self.jobs.open_list(),
+            ExplorerEvent::CancelJob => {
+                self.log_string = match self.jobs.cancel() {
+                    Some(command) => format!("Cancelled {command}"),
+                    None => "Error: no job is running".to_string(),
+                }
+            }
+            ExplorerEvent::TogglePreview => self.preview.enabled = !self.preview.enabled,
+            ExplorerEvent::View => {
+                if let Err(e) = self.viewer.open(&explorer.focused_path()) {
+                    self.log_string = format!("Error: {e}");
+                }
+            }
+            ExplorerEvent::ToggleOutput => match self.jobs.shown() {
+                Some(_) => self.output_expanded = !self.output_expanded,
+                None => self.log_string = "Error: no command has been run".to_string(),
+            },
+            ExplorerEvent::ScrollLog(d) => {
+                self.jobs.scroll_by(d);
+                self.log_string.clear();
+            }
+            ExplorerEvent::Input { event } => {
+                self.input.set_event(*event);
+                self.input.set_active(true);
+            }
+            ExplorerEvent::Confirmation { event } => {
+                self.confirmation.set(*event);
+            }
+            ExplorerEvent::Find => self.finder.open(explorer.root(), explorer.filter()),
+            ExplorerEvent::Search | ExplorerEvent::FilterEntries => match text {
+                // The query is applied as it is typed, so accepting it only has to keep it.
+                Some(_) => explorer.finish_search(),
+                None => {
+                    explorer.begin_search();
+                    self.input.set_event(event);
+                    self.input.set_active(true);
+                }
+            },
+            ExplorerEvent::NextMatch | ExplorerEvent::PreviousMatch => {
+                let forward = matches!(event, ExplorerEvent::NextMatch);
+                if let Err(e) = explorer.next_match(forward) {
+                    self.log_string = format!("Error: {e}");
+                }
+            }
+            ExplorerEvent::ClearSearch => explorer.clear_search(),
+            ExplorerEvent::CycleSort
+            | ExplorerEvent::ReverseSort
+            | ExplorerEvent::ToggleDirsFirst => {
+                explorer.handle_event(event)?;
+                self.log_string = format!("Sorting by {}", explorer.sort().describe());
+            }
+            ExplorerEvent::Grep => match text {
+                Some(pattern) => {
+                    if let Err(e) = self.grep.open(explorer.root(), explorer.filter(), &pattern) {
+                        self.log_string = format!("Error: {e}");
+                    }
+                }
+                None => {
+                    self.input.set_event(event);
+                    self.input.set_active(true);
+                }
+            },
+            ExplorerEvent::ShowGrep => {
+                if !self.grep.show() {
+                    self.log_string = "Error: nothing has been searched for".to_string();
+                }
+            }
+            ExplorerEvent::Paste => self.paste(explorer),
+
+            ExplorerEvent::CreateFile { name } => {
+                let name = expand(explorer, &name, &text);
+                self.log_file_op("Created", explorer.create_file(&name).map(|x| vec![x]));
+            }
+            ExplorerEvent::CreateDir { name } => {
+                let name = expand(explorer, &name, &text);
+                self.log_file_op("Created", explorer.create_dir(&name).map(|x| vec![x]));
+            }
+            ExplorerEvent::Rename { name } => {
+                let name = expand(explorer, &name, &text);
+                self.log_file_op("Renamed to", explorer.rename(&name).map(|x| vec![x]));
+            }
+            ExplorerEvent::CopyTo { to } => {
+                let to = expand(explorer, &to, &text);
+                self.log_file_op("Copied to", explorer.copy(&to));
+            }
+            ExplorerEvent::MoveTo { to } => {
+                let to = expand(explorer, &to, &text);
+                self.log_file_op("Moved to", explorer.move_to(&to));
+            }
+            ExplorerEvent::Delete => self.log_file_op("Deleted", explorer.delete()),
+            ExplorerEvent::Trash => self.log_file_op("Trashed", explorer.trash()),
+            ExplorerEvent::ShowTrash => {
+                if let Err(e) = self.trash.open() {
+                    self.log_string = format!("Error: {e}");
+                }
+            }
+
+            ExplorerEvent::Undo => {
+                self.log_string = match explorer.undo() {
+                    Ok(description) => format!("Undid: {description}"),
+                    Err(e) => format!("Error: {e}"),
+                }
+            }
+            ExplorerEvent::Redo => {
+                self.log_string = match explorer.redo() {
+                    Ok(description) => format!("Redid: {description}"),
+                    Err(e) => format!("Error: {e}"),
+                }
+            }
+            ExplorerEvent::Journal => self.log_string = explorer.journal_summary(4),
+
+            _ => {
+                if let Err(e) = explorer.handle_event(event) {
+                    self.log_string = format!("Error: {e}");
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Scrolls the expanded output with the arrow keys, or closes it.
+    /// Returns false if the key should be handled by the bindings instead.
+    pub fn handle_output_key(&mut self, k: KeyEvent) -> bool {
+        if !(k.kind == KeyEventKind::Press || k.kind == KeyEventKind::Repeat) {
+            return false;
+        }
+        match k.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.output_expanded = false,
+            KeyCode::Up => self.jobs.scroll_by(-1),
+            KeyCode::Down => self.jobs.scroll_by(1),
+            KeyCode::PageUp => self.jobs.scroll_by(-OUTPUT_PAGE),
+            KeyCode::PageDown => self.jobs.scroll_by(OUTPUT_PAGE),
+            KeyCode::Home => self.jobs.scroll_by(i32::MIN),
+            KeyCode::End => self.jobs.scroll_by(i32::MAX),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Starts a command in the background, or suspends the window to run it if it's interactive.
+    fn run_sh(&mut self, sh: PreparedSh, interactive: bool) {
+        match interactive {
+            true => self.suspended = Some(sh),
+            false => {
+                self.jobs.spawn(sh);
+                self.log_string.clear();
+            }
+        }
+    }
+
+    /// Applies a search to the explorer as its query is typed.
+    pub fn input_changed(&mut self, explorer: &mut Explorer) {
+        match self.input.pending_event() {
+            Some(ExplorerEvent::Search) => explorer.set_search(self.input.get_text(), false),
+            Some(ExplorerEvent::FilterEntries) => explorer.set_search(self.input.get_text(), true),
+            _ => {}
+        }
+    }
+
+    /// Clears the input, cancelling a search if that is what was being typed.
+    pub fn cancel_input(&mut self, explorer: &mut Explorer) {
+        if let Some(ExplorerEvent::Search | ExplorerEvent::FilterEntries) = self.input.take_event()
+        {
+            explorer.cancel_search();
+        }
+        self.input.set_active(false);
+        self.input.clear();
+    }
+
+    /// Reveals a result picked in the grep results, then runs the configured open command on it.
+    pub fn grep_event(
+        &mut self,
+        event: GrepEvent,
+        explorer: &mut Explorer,
+        config: &Config,
+    ) -> anyhow::Result<bool> {
+        let (path, line, open) = match event {
+            GrepEvent::Close => return Ok(false),
+            GrepEvent::Reveal(path, line) => (path, line, false),
+            GrepEvent::Open(path, line) => (path, line, true),
+        };
+        if let Err(e) = explorer.reveal(&path) {
+            self.log_string = format!("Error: {e}");
+            return Ok(false);
+        }
+        explorer.set_line(line);
+        match config.grep.open.clone().filter(|_| open) {
+            Some(event) => self.run_event(event, explorer, None),
+            None => Ok(false),
+        }
+    }
+
+    /// Restores an item picked in the trash view.
+    pub fn restore(&mut self, item: &TrashItem, explorer: &mut Explorer) {
+        self.log_file_op("Restored", explorer.restore(item).map(|x| vec![x]));
+    }
+
+    /// Writes the outcome of a file operation to the log.
+    fn log_file_op(&mut self, action: &str, res: anyhow::Result<Vec<PathBuf>>) {
+        self.log_string = match res {
+            Ok(paths) => format!(
+                "{action} {}",
+                paths
+                    .iter()
+                    .map(|x| x.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(e) => format!("Error: {e}"),
+        };
+    }
+}
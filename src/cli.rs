@@ -0,0 +1,173 @@
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io,
+    os::fd::{AsFd, AsRawFd},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, bail};
+
+pub const HELP: &str = "\
+An IDE like file explorer for your terminal.
+
+Usage: idex [OPTIONS] [PATH]
+
+Arguments:
+  [PATH]  The directory to open, the current directory if not given
+
+Options:
+      --config <FILE>         Read the config from FILE instead of the config directory
+      --no-config             Ignore the config file, using the default config
+      --reveal <PATH>         Open with PATH selected and the directories leading up to it expanded
+      --fresh                 Start collapsed, instead of where the last session in PATH left off
+      --pick                  Pick files with enter, then print their paths once idex quits
+      --print-default-config  Print the default config and exit
+      --check-config          Check that the config can be read and exit
+  -h, --help                  Print this help and exit
+  -V, --version               Print the version and exit";
+
+/// Something to do instead of opening the explorer.
+pub enum Action {
+    PrintDefaultConfig,
+    CheckConfig,
+    Help,
+    Version,
+}
+
+#[derive(Default)]
+pub struct Cli {
+    pub path: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub no_config: bool,
+    pub reveal: Option<PathBuf>,
+    pub fresh: bool,
+    // Chooser mode, where the picked paths are printed to stdout.
+    pub pick: bool,
+    pub action: Option<Action>,
+}
+
+impl Cli {
+    /// Parses the arguments given to the program, not including the program's name.
+    /// Options that take a value accept it as the next argument or after an `=`.
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> anyhow::Result<Self> {
+        let mut cli = Self::default();
+        let mut args = args.into_iter();
+        let mut options_done = false;
+        while let Some(arg) = args.next() {
+            let text = arg.to_string_lossy();
+            if options_done || !text.starts_with('-') || text == "-" {
+                if cli.path.is_some() {
+                    bail!("only one path can be opened, see --help");
+                }
+                cli.path = Some(PathBuf::from(arg));
+                continue;
+            }
+
+            let (name, inline) = match text.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(OsString::from(value))),
+                None => (text.to_string(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .map(PathBuf::from)
+                    .ok_or(anyhow!("{name} needs a value, see --help"))
+            };
+            match name.as_str() {
+                "--config" => cli.config = Some(value()?),
+                "--reveal" => cli.reveal = Some(value()?),
+                "--no-config" => cli.no_config = true,
+                "--fresh" => cli.fresh = true,
+                "--pick" => cli.pick = true,
+                "--print-default-config" => cli.action = Some(Action::PrintDefaultConfig),
+                "--check-config" => cli.action = Some(Action::CheckConfig),
+                "-h" | "--help" => cli.action = Some(Action::Help),
+                "-V" | "--version" => cli.action = Some(Action::Version),
+                // Everything after `--` is a path, even if it starts with a dash.
+                "--" => options_done = true,
+                _ => bail!("unknown option {name}, see --help"),
+            }
+        }
+
+        if cli.config.is_some() && cli.no_config {
+            bail!("--config and --no-config can't be used together");
+        }
+        Ok(cli)
+    }
+}
+
+/// Points stdout at the terminal, so the window is drawn there while the picked paths go to
+/// wherever stdout was going, like a `$(idex --pick)`. Returns the original stdout.
+pub fn redirect_stdout() -> anyhow::Result<File> {
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| anyhow!("failed to open the terminal: {e}"))?;
+    let stdout = io::stdout().as_fd().try_clone_to_owned()?;
+    // SAFETY: both are open file descriptors, and stdout is only ever replaced by another one.
+    if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDOUT_FILENO) } == -1 {
+        bail!(
+            "failed to draw on the terminal: {}",
+            io::Error::last_os_error()
+        );
+    }
+    Ok(File::from(stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf};
+
+    use super::Cli;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Cli> {
+        Cli::parse(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn values_follow_the_option_or_an_equals_sign() {
+        let cli = parse(&["--config", "a.toml", "--reveal=src/main.rs", "dir"]).unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("a.toml")));
+        assert_eq!(cli.reveal, Some(PathBuf::from("src/main.rs")));
+        assert_eq!(cli.path, Some(PathBuf::from("dir")));
+
+        // Only the first `=` separates the value.
+        let cli = parse(&["--config=a=b.toml"]).unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("a=b.toml")));
+    }
+
+    #[test]
+    fn everything_after_a_double_dash_is_a_path() {
+        let cli = parse(&["--fresh", "--", "--pick"]).unwrap();
+        assert!(cli.fresh);
+        assert!(!cli.pick);
+        assert_eq!(cli.path, Some(PathBuf::from("--pick")));
+    }
+
+    #[test]
+    fn a_missing_value_is_an_error() {
+        assert!(parse(&["--reveal"]).is_err());
+        assert!(parse(&["dir", "--config"]).is_err());
+    }
+
+    #[test]
+    fn only_one_path_can_be_given() {
+        assert!(parse(&["a", "b"]).is_err());
+        assert!(parse(&["a", "--", "b"]).is_err());
+    }
+
+    #[test]
+    fn config_and_no_config_conflict() {
+        assert!(parse(&["--config", "a.toml", "--no-config"]).is_err());
+        assert!(parse(&["--no-config"]).unwrap().no_config);
+    }
+
+    #[test]
+    fn unknown_options_are_an_error() {
+        assert!(parse(&["--nope"]).is_err());
+        assert!(parse(&["-x"]).is_err());
+    }
+}
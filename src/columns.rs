@@ -0,0 +1,106 @@
+use ascii_forge::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ColumnsConfig,
+    display,
+    entry::{Entry, EntryType},
+};
+
+/// A piece of metadata shown to the right of each entry.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Column {
+    // Directories only have a size once it has been worked out with `dir_size`.
+    Size,
+    Modified,
+    Permissions,
+    Owner,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    // Like `5m ago`.
+    #[default]
+    Relative,
+    // Like `2024-01-31 12:00`.
+    Absolute,
+}
+
+impl Column {
+    /// How many characters the column takes up, so every row lines up.
+    fn width(self, config: &ColumnsConfig) -> usize {
+        match self {
+            Self::Size => 6,
+            Self::Modified => match config.time {
+                TimeFormat::Relative => 8,
+                TimeFormat::Absolute => 16,
+            },
+            Self::Permissions => 10,
+            Self::Owner => 8,
+        }
+    }
+
+    fn text(self, entry: &Entry, config: &ColumnsConfig) -> String {
+        // An entry with an error may not have been read, so none of its metadata can be trusted.
+        if entry.error.is_some() {
+            return "-".to_string();
+        }
+        match self {
+            Self::Size => match (entry.entry_type, entry.dir_size) {
+                (EntryType::File | EntryType::Symlink, _) => display::human_size(entry.size),
+                (EntryType::Dir, Some(size)) => display::human_size(size),
+                (EntryType::Dir, None) => "-".to_string(),
+            },
+            Self::Modified => match (entry.modified, config.time) {
+                (None, _) => "-".to_string(),
+                (Some(time), TimeFormat::Relative) => display::relative_time(time),
+                (Some(time), TimeFormat::Absolute) => display::local_time(time)[..16].to_string(),
+            },
+            Self::Permissions => display::permissions(entry.mode),
+            Self::Owner => display::user_name(entry.uid),
+        }
+    }
+}
+
+/// Returns the entry's columns in the configured order, padded to line up and separated by two spaces.
+/// Numbers are aligned to the right, and names to the left.
+pub fn columns(entry: &Entry, config: &ColumnsConfig) -> String {
+    let mut text = String::new();
+    for column in &config.show {
+        let width = column.width(config);
+        let value = column
+            .text(entry, config)
+            .chars()
+            .take(width)
+            .collect::<String>();
+        match column {
+            Column::Owner | Column::Permissions => text.push_str(&format!("  {value:<width$}")),
+            Column::Size | Column::Modified => text.push_str(&format!("  {value:>width$}")),
+        }
+    }
+    text
+}
+
+/// Cuts the name down to at most `width` characters, ending it with `…` if anything was cut.
+pub fn truncate(name: &str, width: usize) -> String {
+    if name.chars().count() <= width {
+        return name.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut truncated = name
+        .chars()
+        .take(width.saturating_sub(1))
+        .collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+/// Renders the columns at the right edge of the buffer's line, in a dim color.
+pub fn render(text: String, y: u16, buffer: &mut Buffer) {
+    let x = buffer.size().x.saturating_sub(text.chars().count() as u16);
+    render!(buffer, vec2(x, y) => [ text.dark_grey() ]);
+}
@@ -1,11 +1,24 @@
-use std::{collections::HashMap, fs::File, io::Read, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
 
 use crokey::KeyCombination;
 use crossterm::style::Color;
 use globset::Glob;
 use serde::{Deserialize, Serialize};
 
-use crate::{events::ExplorerEvent, style::Style};
+use crate::{
+    columns::{Column, TimeFormat},
+    events::ExplorerEvent,
+    git::GitState,
+    sort::SortMode,
+    style::Style,
+};
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -23,11 +36,204 @@ impl Default for TabConfig {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub enabled: bool,
+    pub show_hidden: bool,
+    pub git_ignore: bool,
+    pub exclude: Vec<Glob>,
+    // Whether links to directories can be expanded, and are searched through by the finder and grep.
+    pub follow_dir_links: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            show_hidden: true,
+            git_ignore: true,
+            exclude: vec![],
+            follow_dir_links: true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SortConfig {
+    pub mode: SortMode,
+    pub reverse: bool,
+    // Whether directories are listed before files, otherwise they are mixed in.
+    pub dirs_first: bool,
+}
+
+impl Default for SortConfig {
+    fn default() -> Self {
+        Self {
+            mode: SortMode::Name,
+            reverse: false,
+            dirs_first: true,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColumnsConfig {
+    // The columns shown to the right of each entry, in order.
+    pub show: Vec<Column>,
+    pub time: TimeFormat,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitConfig {
+    pub enabled: bool,
+
+    pub modified: Style,
+    pub staged: Style,
+    pub untracked: Style,
+    pub ignored: Style,
+    pub conflicted: Style,
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        let style = |icon: &str, color| Style {
+            icon: icon.to_string(),
+            icon_color: Some(color),
+            text_color: None,
+        };
+        Self {
+            enabled: true,
+            modified: style("M", Color::Yellow),
+            staged: style("S", Color::Green),
+            untracked: style("U", Color::Green),
+            ignored: style("I", Color::DarkGrey),
+            conflicted: style("!", Color::Red),
+        }
+    }
+}
+
+impl GitConfig {
+    pub fn style(&self, state: GitState) -> &Style {
+        match state {
+            GitState::Modified => &self.modified,
+            GitState::Staged => &self.staged,
+            GitState::Untracked => &self.untracked,
+            GitState::Ignored => &self.ignored,
+            GitState::Conflicted => &self.conflicted,
+        }
+    }
+}
+
+/// A command that previews files matching a glob, its output is shown instead of the built in preview.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Previewer {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewConfig {
+    pub enabled: bool,
+    // The percentage of the window's width taken up by the preview.
+    pub width: u16,
+    // How much of a file, or of what a previewer command writes, is read to preview it.
+    pub max_bytes: u64,
+
+    #[serde(with = "tuple_vec_map")]
+    pub commands: Vec<(Glob, Previewer)>,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            width: 50,
+            max_bytes: 64 * 1024,
+            commands: vec![],
+        }
+    }
+}
+
+impl PreviewConfig {
+    pub fn find_command(&self, name: &str) -> Option<&Previewer> {
+        for (glob, previewer) in &self.commands {
+            if glob.compile_matcher().is_match(name.to_lowercase()) {
+                return Some(previewer);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewerConfig {
+    // The name of one of the bundled highlighting themes.
+    pub theme: String,
+    // How much of a file is read to view it.
+    pub max_bytes: u64,
+}
+
+impl Default for ViewerConfig {
+    fn default() -> Self {
+        Self {
+            theme: "base16-ocean.dark".to_string(),
+            max_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrepConfig {
+    // Run on a result picked with `o`, with `$LINE` set to the result's line.
+    pub open: Option<ExplorerEvent>,
+    // The search stops once it has found this many matches.
+    pub max_matches: usize,
+}
+
+impl Default for GrepConfig {
+    fn default() -> Self {
+        Self {
+            open: None,
+            max_matches: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    // How many lines of each job's output are kept, the oldest ones are dropped past this.
+    pub max_lines: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self { max_lines: 10_000 }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub tab: TabConfig,
 
+    pub filter: FilterConfig,
+    pub sort: SortConfig,
+    pub columns: ColumnsConfig,
+    pub git: GitConfig,
+    pub preview: PreviewConfig,
+    pub viewer: ViewerConfig,
+    pub grep: GrepConfig,
+    pub jobs: JobsConfig,
+
     #[serde(rename = "double_click_delay")]
     pub double_click_ms_delay: u128,
 
@@ -36,6 +242,7 @@ pub struct Config {
     pub double_click: Option<ExplorerEvent>,
 
     pub folder: Style,
+    pub mark: Style,
     #[serde(rename = "style")]
     #[serde(with = "tuple_vec_map")]
     pub styles: Vec<(Glob, Style)>,
@@ -45,34 +252,66 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             tab: TabConfig::default(),
+            filter: FilterConfig::default(),
+            sort: SortConfig::default(),
+            columns: ColumnsConfig::default(),
+            git: GitConfig::default(),
+            preview: PreviewConfig::default(),
+            viewer: ViewerConfig::default(),
+            grep: GrepConfig::default(),
+            jobs: JobsConfig::default(),
             double_click_ms_delay: 500,
             bindings: HashMap::new(),
             double_click: None,
             folder: Style::default(),
+            mark: Style {
+                icon: "*".to_string(),
+                icon_color: Some(Color::Yellow),
+                text_color: None,
+            },
             styles: vec![],
         }
     }
 }
 
+pub const DEFAULT_CONFIG: &str = include_str!("../default_config/conf.toml");
+
 impl Config {
-    pub fn load() -> anyhow::Result<Self> {
+    /// Returns where the config is read from when no other file is given.
+    pub fn default_path() -> PathBuf {
         let mut home_path = dirs::home_dir().unwrap();
         home_path.push(".config");
 
         let conf_home = std::env::var("XDG_CONFIG_HOME")
             .map(|x| PathBuf::from_str(&x).unwrap())
             .unwrap_or(home_path);
-        let conf_path = conf_home.join("idex/conf.toml");
+        conf_home.join("idex/conf.toml")
+    }
 
-        let mut config_text = String::new();
-        match File::open(conf_path) {
-            Ok(mut t) => {
-                t.read_to_string(&mut config_text)?;
+    /// Reads the config at `path`, which has to exist, or at the default path,
+    /// falling back to the default config if there is nothing there.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let (path, config_text) = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| anyhow!("failed to read {}: {e}", path.display()))?;
+                (path.to_path_buf(), text)
             }
-            Err(_) => config_text = include_str!("../default_config/conf.toml").to_string(),
-        }
+            None => {
+                let path = Self::default_path();
+                match fs::read_to_string(&path) {
+                    Ok(text) => (path, text),
+                    Err(_) => return Self::default_config(),
+                }
+            }
+        };
+
+        toml::from_str(&config_text)
+            .map_err(|e| anyhow!("{} is not a valid config: {e}", path.display()))
+    }
 
-        Ok(toml::from_str(&config_text)?)
+    pub fn default_config() -> anyhow::Result<Self> {
+        Ok(toml::from_str(DEFAULT_CONFIG)?)
     }
 
     pub fn find_match(&self, name: &str) -> Option<Style> {
@@ -1,12 +1,101 @@
-use std::path::Path;
+use std::{
+    fs,
+    path::Path,
+    sync::mpsc::{channel, Receiver},
+    thread,
+};
 
-use crate::entry::Entry;
+use anyhow::anyhow;
+
+use crate::{config::SortConfig, entry::Entry, filter::Filter};
+
+// How many entries are read before the rest of a directory is left to a background thread,
+// and how many are sent back from it at a time.
+const PAGE_SIZE: usize = 1000;
+
+/// A directory's entries, with the rest still being read in the background if it is large.
+pub struct Listing {
+    pub entries: Vec<Entry>,
+    pub rest: Option<Receiver<Vec<Entry>>>,
+}
+
+/// Reads the first page of the directory's entries, leaving the rest to a background thread
+/// so that huge directories can be shown before they have been read in full.
+pub fn dir_listing(
+    path: &Path,
+    depth: usize,
+    filter: &Filter,
+    sort: &SortConfig,
+) -> anyhow::Result<Listing> {
+    // The walker reports a directory it can't read as one of its entries, so that is checked first.
+    fs::read_dir(path).map_err(|e| anyhow!("failed to read {}: {e}", path.display()))?;
+
+    let dir = path.to_path_buf();
+    // Links are listed as links, so broken ones and loops can be shown rather than failing the walk.
+    let mut walker = filter
+        .walker(path)
+        .max_depth(Some(1))
+        .follow_links(false)
+        .build()
+        .filter_map(move |x| match x {
+            // The walker always yields the directory itself first.
+            Ok(entry) if entry.depth() == 0 => None,
+            Ok(entry) => Some(Entry::new(entry.path(), depth)),
+            // Anything else it fails on is shown as an entry with the error,
+            // as long as it can tell which of the directory's entries that is.
+            Err(e) => error_path(&e)
+                .filter(|x| x.parent() == Some(&dir))
+                .map(|x| Entry::failed(x, depth, e.to_string())),
+        });
 
-pub fn dir_items(path: &Path, depth: usize) -> anyhow::Result<Vec<Entry>> {
     let mut entries = vec![];
-    for entry in path.read_dir()? {
-        entries.push(Entry::new(entry?, depth))
+    for entry in walker.by_ref() {
+        entries.push(entry);
+        if entries.len() == PAGE_SIZE {
+            break;
+        }
+    }
+    entries.sort_by(|a, b| sort.compare(a, b));
+    if entries.len() < PAGE_SIZE {
+        return Ok(Listing {
+            entries,
+            rest: None,
+        });
+    }
+
+    let (sender, receiver) = channel();
+    let sort = *sort;
+    thread::spawn(move || {
+        let mut batch = vec![];
+        for entry in walker {
+            batch.push(entry);
+            if batch.len() < PAGE_SIZE {
+                continue;
+            }
+            // Batches are sorted, so they only have to be merged in with what was read before.
+            batch.sort_by(|a, b| sort.compare(a, b));
+            // The listing has been dropped once the receiver is, like when the directory is collapsed.
+            if sender.send(std::mem::take(&mut batch)).is_err() {
+                return;
+            }
+        }
+        batch.sort_by(|a, b| sort.compare(a, b));
+        let _ = sender.send(batch);
+    });
+    Ok(Listing {
+        entries,
+        rest: Some(receiver),
+    })
+}
+
+/// Returns the path the walker failed on, if the error has one.
+fn error_path(error: &ignore::Error) -> Option<&Path> {
+    match error {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            error_path(err)
+        }
+        ignore::Error::Loop { child, .. } => Some(child),
+        _ => None,
     }
-    entries.sort();
-    Ok(entries)
 }
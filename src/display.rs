@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Formats a time in the local time zone as `YYYY-MM-DD hh:mm:ss`.
+pub fn local_time(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(x) => x.as_secs() as libc::time_t,
+        Err(e) => -(e.duration().as_secs() as libc::time_t),
+    };
+    // SAFETY: `tm` is plain data, and `localtime_r` only writes to it.
+    let tm = unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Formats how long ago a time was, like `5m ago` or `3d ago`.
+pub fn relative_time(time: SystemTime) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    const MONTH: u64 = 30 * DAY;
+    const YEAR: u64 = 365 * DAY;
+
+    // Times in the future are treated as now.
+    let secs = SystemTime::now()
+        .duration_since(time)
+        .map_or(0, |x| x.as_secs());
+    match secs {
+        0..MINUTE => "now".to_string(),
+        MINUTE..HOUR => format!("{}m ago", secs / MINUTE),
+        HOUR..DAY => format!("{}h ago", secs / HOUR),
+        DAY..MONTH => format!("{}d ago", secs / DAY),
+        MONTH..YEAR => format!("{}mo ago", secs / MONTH),
+        _ => format!("{}y ago", secs / YEAR),
+    }
+}
+
+/// Returns the name of the user with the uid, or the uid itself if it has no name.
+/// Names are cached, since looking them up can mean reading `/etc/passwd`.
+pub fn user_name(uid: u32) -> String {
+    static NAMES: OnceLock<Mutex<HashMap<u32, String>>> = OnceLock::new();
+    let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+    names
+        .entry(uid)
+        .or_insert_with(|| lookup_user(uid).unwrap_or_else(|| uid.to_string()))
+        .clone()
+}
+
+fn lookup_user(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: `passwd` is plain data, and its strings point into `buf`, which outlives it.
+    unsafe {
+        let mut passwd = std::mem::zeroed::<libc::passwd>();
+        let ret = libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result);
+        if ret != 0 || result.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr(passwd.pw_name)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// Formats a number of bytes with a binary unit, like `1.5K` or `12M`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "K", "M", "G", "T", "P"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit == 0 || size >= 10.0 {
+        true => format!("{}{}", size.round(), UNITS[unit]),
+        false => format!("{size:.1}{}", UNITS[unit]),
+    }
+}
+
+/// Formats a unix mode like `ls -l` does, such as `drwxr-xr-x`.
+pub fn permissions(mode: u32) -> String {
+    // `mode_t` is only 16 bits on macOS, so its constants are widened to match the mode.
+    #[allow(clippy::unnecessary_cast)]
+    let kind = [
+        (libc::S_IFDIR as u32, 'd'),
+        (libc::S_IFLNK as u32, 'l'),
+        (libc::S_IFIFO as u32, 'p'),
+        (libc::S_IFSOCK as u32, 's'),
+        (libc::S_IFCHR as u32, 'c'),
+        (libc::S_IFBLK as u32, 'b'),
+    ]
+    .into_iter()
+    .find(|x| mode & libc::S_IFMT as u32 == x.0)
+    .map_or('-', |x| x.1);
+    let mut text = String::from(kind);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    text
+}
@@ -1,13 +1,38 @@
-use std::{cmp::Ordering, fs::DirEntry, path::PathBuf};
+use std::{
+    fs,
+    ops::Range,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 use ascii_forge::prelude::*;
 
-use crate::{config::Config, dir_items::dir_items};
+use crate::{
+    columns,
+    config::{Config, SortConfig},
+    dir_items::{dir_listing, Listing},
+    filter::Filter,
+    git::GitState,
+};
+
+// Columns are hidden when they would leave less room than this for the name.
+const MIN_NAME_WIDTH: u16 = 12;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EntryType {
     Dir = 1,
     File = 2,
+    Symlink = 3,
+}
+
+/// Where a symbolic link points.
+#[derive(Clone)]
+pub struct Link {
+    // The target as it is written in the link, which may be relative to the link's directory.
+    pub target: PathBuf,
+    // What the link ends up at after following every link, or `None` if it is broken.
+    pub resolved: Option<EntryType>,
 }
 
 #[derive(Clone)]
@@ -20,6 +45,15 @@ pub struct Entry {
     pub file_name: String,
 
     pub entry_type: EntryType,
+    pub link: Option<Link>,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub mode: u32,
+    pub uid: u32,
+    // The size of everything inside of a directory, once it has been worked out.
+    pub dir_size: Option<u64>,
+    // Why the entry couldn't be read, or why the directory couldn't be expanded.
+    pub error: Option<String>,
 }
 
 impl PartialEq for Entry {
@@ -30,74 +64,300 @@ impl PartialEq for Entry {
 
 impl Eq for Entry {}
 
-impl PartialOrd for Entry {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
+impl Entry {
+    pub fn new(path: &Path, depth: usize) -> Self {
+        let data = match path.symlink_metadata() {
+            Ok(data) => data,
+            Err(e) => {
+                let error = format!("failed to read {}: {e}", path.display());
+                return Self::failed(path, depth, error);
+            }
+        };
 
-impl Ord for Entry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let ord = self.entry_type.cmp(&other.entry_type);
-        match ord {
-            Ordering::Equal => self.file_name.cmp(&other.file_name),
-            _ => ord,
+        // Links are kept as they are, rather than being replaced by their targets.
+        let link = data.is_symlink().then(|| Link {
+            target: fs::read_link(path).unwrap_or_default(),
+            resolved: fs::metadata(path).ok().map(|x| match x.is_dir() {
+                true => EntryType::Dir,
+                false => EntryType::File,
+            }),
+        });
+        Self {
+            depth,
+            expanded: false,
+            path: path.to_path_buf(),
+            file_name: file_name(path),
+            entry_type: match (data.is_dir(), &link) {
+                (_, Some(_)) => EntryType::Symlink,
+                (true, None) => EntryType::Dir,
+                (false, None) => EntryType::File,
+            },
+            link,
+            size: data.len(),
+            modified: data.modified().ok(),
+            mode: data.mode(),
+            uid: data.uid(),
+            dir_size: None,
+            error: None,
         }
     }
-}
 
-impl Entry {
-    pub fn new(entry: DirEntry, depth: usize) -> Self {
-        let data = entry.metadata().unwrap();
-        let path = entry.path().canonicalize().unwrap();
+    /// Creates an entry for a path that couldn't be read, which shows the error instead of its metadata.
+    pub fn failed(path: &Path, depth: usize, error: String) -> Self {
         Self {
             depth,
             expanded: false,
-            path,
-            file_name: entry.file_name().into_string().unwrap(),
-            entry_type: match data.is_dir() {
-                true => EntryType::Dir,
-                false => EntryType::File,
-            },
+            path: path.to_path_buf(),
+            file_name: file_name(path),
+            entry_type: EntryType::File,
+            link: None,
+            size: 0,
+            modified: None,
+            mode: 0,
+            uid: 0,
+            dir_size: None,
+            error: Some(error),
         }
     }
 
-    pub fn expand(&mut self) -> anyhow::Result<Option<Vec<Entry>>> {
+    pub fn expand(
+        &mut self,
+        filter: &Filter,
+        sort: &SortConfig,
+    ) -> anyhow::Result<Option<Listing>> {
         if self.expanded {
             return Ok(None);
         }
-        self.expanded = true;
-        match self.entry_type {
-            EntryType::Dir => {
-                let entries = dir_items(&self.path, self.depth + 1)?;
-                Ok(Some(entries))
+        if !self.is_dir() {
+            self.expanded = true;
+            return Ok(None);
+        }
+        // A directory that can't be read stays collapsed, and is marked until it can be.
+        match dir_listing(&self.path, self.depth + 1, filter, sort) {
+            Ok(listing) => {
+                self.expanded = true;
+                self.error = None;
+                Ok(Some(listing))
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns whether the entry is a directory, or a link that ends up at one.
+    pub fn is_dir(&self) -> bool {
+        match &self.link {
+            Some(link) => link.resolved == Some(EntryType::Dir),
+            None => self.entry_type == EntryType::Dir,
+        }
+    }
+
+    /// Returns whether the entry is a link that doesn't end up anywhere.
+    pub fn is_broken_link(&self) -> bool {
+        self.link.as_ref().is_some_and(|x| x.resolved.is_none())
+    }
+
+    /// Returns the link's real target if expanding it would never end, because the target contains
+    /// the link itself, or is the same directory as one of the expanded `ancestors` it is shown in.
+    pub fn link_cycle<'a>(&self, ancestors: impl IntoIterator<Item = &'a Path>) -> Option<PathBuf> {
+        self.link.as_ref()?;
+        let target = fs::canonicalize(&self.path).ok()?;
+        let dir = fs::canonicalize(self.path.parent()?).ok()?;
+        let cycle = dir.starts_with(&target)
+            || ancestors
+                .into_iter()
+                .any(|x| fs::canonicalize(x).is_ok_and(|x| x == target));
+        cycle.then_some(target)
+    }
+
+    /// Returns where the query is in the entry's name, ignoring case unless the query has an uppercase letter.
+    pub fn search_match(&self, query: &str) -> Option<Range<usize>> {
+        match query.chars().any(char::is_uppercase) {
+            true => {
+                let start = self.file_name.find(query)?;
+                Some(start..start + query.len())
             }
-            EntryType::File => Ok(None),
+            false => find_ignoring_case(&self.file_name, query),
         }
     }
 
-    pub fn render(&self, pos: Vec2, buffer: &mut Buffer, selected: bool, config: &Config) {
-        match self.entry_type {
-            EntryType::Dir => {
-                let style = config
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        pos: Vec2,
+        buffer: &mut Buffer,
+        selected: bool,
+        marked: bool,
+        git: Option<GitState>,
+        search: Option<&str>,
+        config: &Config,
+    ) {
+        let (mut style, suffix) = match self.is_dir() {
+            true => (
+                config
                     .find_match(&self.file_name)
-                    .unwrap_or(config.folder.clone());
+                    .unwrap_or(config.folder.clone()),
+                "/",
+            ),
+            false => (config.find_match(&self.file_name).unwrap_or_default(), ""),
+        };
+        if self.is_broken_link() || self.error.is_some() {
+            style.text_color = Some(Color::Red);
+        }
 
-                if selected {
-                    render!(buffer, pos => [ config.tab.text.repeat(self.depth - 1).with(config.tab.color), " > ", style, style.style(self.file_name.clone()), "/ <" ]);
-                } else {
-                    render!(buffer, pos => [ config.tab.text.repeat(self.depth).with(config.tab.color), style, style.style(self.file_name.clone()), "/"]);
-                }
+        let git_style = git.map(|x| config.git.style(x));
+        if let Some(color) = git_style.and_then(|x| x.text_color) {
+            style.text_color = Some(color);
+        }
+
+        let mut loc = if selected {
+            render!(buffer, pos => [ config.tab.text.repeat(self.depth - 1).with(config.tab.color), " > " ])
+        } else {
+            render!(buffer, pos => [ config.tab.text.repeat(self.depth).with(config.tab.color) ])
+        };
+        if marked {
+            loc = render!(buffer, loc => [ config.mark ]);
+        }
+        loc = render!(buffer, loc => [ style ]);
+
+        // Columns go at the right edge, unless the name would be left with too little room.
+        let columns = columns::columns(self, &config.columns);
+        let right = match buffer
+            .size()
+            .x
+            .saturating_sub(columns.chars().count() as u16)
+        {
+            x if !columns.is_empty() && x >= loc.x + MIN_NAME_WIDTH => {
+                columns::render(columns, pos.y, buffer);
+                x
+            }
+            _ => buffer.size().x,
+        };
+
+        // Whatever follows the name has to fit before the columns too.
+        let git_width = git_style
+            .filter(|x| !x.icon.is_empty())
+            .map_or(0, |x| x.icon.chars().count() + 2);
+        let end_width = match (selected, git_width) {
+            (false, _) => 0,
+            (true, 0) => 2,
+            (true, _) => 1,
+        };
+        let room = (right.saturating_sub(loc.x) as usize)
+            .saturating_sub(suffix.len() + git_width + end_width + 1);
+        let name = columns::truncate(&self.file_name, room);
+        // Links show their target after the name, with whatever room is left.
+        let link = self.link.as_ref().map(|x| {
+            let target = format!(" -> {}", x.target.display());
+            let room = room.saturating_sub(name.chars().count() + suffix.len());
+            columns::truncate(&target, room)
+        });
+        // Errors are marked, and explained when the entry is selected.
+        let error = self.error.as_ref().map(|x| {
+            let error = match selected {
+                true => format!(" ! {x}"),
+                false => " !".to_string(),
+            };
+            let room = room.saturating_sub(name.chars().count() + suffix.len());
+            columns::truncate(&error, room)
+        });
+
+        loc = match search.and_then(|x| self.search_match(x)) {
+            Some(range) => {
+                // Only the part of the match that wasn't cut off is highlighted.
+                let kept = match name.len() == self.file_name.len() {
+                    true => name.len(),
+                    false => name.len() - '…'.len_utf8(),
+                };
+                let (start, end) = (range.start.min(kept), range.end.min(kept));
+                render!(buffer, loc => [
+                    style.style(name[..start].to_string()),
+                    name[start..end].to_string().black().on_yellow(),
+                    style.style(name[end..].to_string()),
+                    suffix
+                ])
             }
-            EntryType::File => {
-                let style = config.find_match(&self.file_name).unwrap_or_default();
+            None => render!(buffer, loc => [ style.style(name), suffix ]),
+        };
+        if let Some(link) = link {
+            loc = match self.is_broken_link() {
+                true => render!(buffer, loc => [ link.red() ]),
+                false => render!(buffer, loc => [ link.dark_grey() ]),
+            };
+        }
+        if let Some(error) = error {
+            loc = render!(buffer, loc => [ error.red() ]);
+        }
+
+        // The style renders its own trailing space after the icon.
+        let mut end = " <";
+        if let Some(git_style) = git_style.filter(|x| !x.icon.is_empty()) {
+            loc = render!(buffer, loc => [ " ", git_style ]);
+            end = "<";
+        }
+        if selected {
+            render!(buffer, loc => [ end ]);
+        }
+    }
+}
 
-                if selected {
-                    render!(buffer, pos => [ config.tab.text.repeat(self.depth - 1).with(config.tab.color), " > ", style, style.style(self.file_name.clone()), " <" ]);
-                } else {
-                    render!(buffer, pos => [ config.tab.text.repeat(self.depth).with(config.tab.color), style, style.style(self.file_name.clone()) ]);
+/// Returns where `query` is in `name`, comparing the lowercase of every character.
+/// Lowercasing can change how long a character is, so the name is walked a character at a time
+/// to keep the range on its own character boundaries.
+fn find_ignoring_case(name: &str, query: &str) -> Option<Range<usize>> {
+    let query = query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        return Some(0..0);
+    }
+    name.char_indices().find_map(|(start, _)| {
+        let mut left = query.as_slice();
+        for (i, c) in name[start..].char_indices() {
+            if left.is_empty() {
+                return Some(start..start + i);
+            }
+            for lower in c.to_lowercase() {
+                match left.split_first() {
+                    Some((first, rest)) if *first == lower => left = rest,
+                    Some(_) => return None,
+                    // The query ends partway through the character, which is still matched as a whole.
+                    None => break,
                 }
             }
         }
+        left.is_empty().then_some(start..name.len())
+    })
+}
+
+/// Returns the name shown for the path.
+/// Names that aren't valid UTF-8 are shown with replacement characters, the path is kept as it is.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or(path.to_string_lossy(), |x| x.to_string_lossy())
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_ignoring_case;
+
+    #[test]
+    fn matches_stay_on_character_boundaries() {
+        // `İ` grows and the Kelvin sign shrinks when lowercased, so the lowercase name is as long as
+        // the name, but its offsets don't line up with it.
+        let name = "İİ\u{212A}k.txt";
+        let range = find_ignoring_case(name, "k").unwrap();
+        assert_eq!(&name[range], "\u{212A}");
+        let range = find_ignoring_case(name, "kk.").unwrap();
+        assert_eq!(&name[range], "\u{212A}k.");
+        // A query that ends partway through a character's lowercase matches the whole character.
+        let range = find_ignoring_case(name, "i\u{307}i").unwrap();
+        assert_eq!(&name[range], "İİ");
+        assert_eq!(find_ignoring_case("Notes.TXT", "s.t"), Some(4..7));
+        assert_eq!(find_ignoring_case("notes", "x"), None);
     }
 }
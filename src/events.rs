@@ -9,8 +9,109 @@ pub enum ExplorerEvent {
     Collapse,
     Quit,
 
-    Sh { command: String, args: Vec<String> },
+    // Marks entries, so that file operations and `$SELECTED` act on all of them.
+    ToggleMark,
+    MarkRange,
+    MarkAll,
+    ClearMarks,
 
-    Input { event: Box<ExplorerEvent> },
-    Confirmation { event: Box<ExplorerEvent> },
+    // Stores the selected entries, then copies (or moves, if cut) them into the focused directory.
+    Yank,
+    Cut,
+    Paste,
+
+    // Steps through the journal of file operations, or shows it in the log.
+    Undo,
+    Redo,
+    Journal,
+
+    // Toggles every filter at once, or just the hiding of dotfiles.
+    ToggleFilter,
+    ToggleHidden,
+
+    // Works out the size of everything inside of the selected directories, for the size column.
+    DirSize,
+
+    // Changes how entries in each directory are ordered: cycles through the sort modes,
+    // reverses the order, or toggles mixing directories in with files.
+    CycleSort,
+    ReverseSort,
+    ToggleDirsFirst,
+
+    // Shows a preview of the focused entry next to the tree.
+    TogglePreview,
+    // Opens the focused file in a read-only viewer, highlighted by its extension.
+    View,
+
+    // Opens the fuzzy finder over every file under the root.
+    Find,
+
+    // Jumps to entries whose names match as the query is typed, or hides every entry that doesn't match
+    // (besides the directories holding matches), then steps through the matches or clears the query.
+    Search,
+    FilterEntries,
+    NextMatch,
+    PreviousMatch,
+    ClearSearch,
+
+    // Searches the contents of every file under the root for a regex, or shows the last results again.
+    Grep,
+    ShowGrep,
+
+    // Shows every background job to pick whose output is in the log, cancels the shown job,
+    // scrolls through its output, or expands it into a popup.
+    Jobs,
+    CancelJob,
+    ScrollLog(i32),
+    ToggleOutput,
+
+    // Runs in the background, with its output shown in the log.
+    // With `each`, the command is run once for every selected entry, with the variables describing that entry.
+    // With `interactive`, the window is suspended and the command takes over the terminal until it exits.
+    Sh {
+        command: String,
+        args: Vec<String>,
+        #[serde(default)]
+        each: bool,
+        #[serde(default)]
+        interactive: bool,
+    },
+    // A single command string run by `$SHELL -c`, so pipes and redirects work.
+    // Variables are substituted as quoted values, so names with spaces or quotes stay one word.
+    Shell {
+        command: String,
+        #[serde(default)]
+        each: bool,
+        #[serde(default)]
+        interactive: bool,
+    },
+
+    // File operations, names are relative to the focused entry and support the same variables as `Sh`.
+    CreateFile {
+        name: String,
+    },
+    CreateDir {
+        name: String,
+    },
+    Rename {
+        name: String,
+    },
+    CopyTo {
+        to: String,
+    },
+    MoveTo {
+        to: String,
+    },
+    Delete,
+
+    // Moves the selected entries to the freedesktop trash, or opens a view to restore them from it.
+    Trash,
+    ShowTrash,
+
+    Input {
+        event: Box<ExplorerEvent>,
+    },
+    Confirmation {
+        event: Box<ExplorerEvent>,
+    },
 }
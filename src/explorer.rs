@@ -1,36 +1,239 @@
-use std::path::{Path, PathBuf};
+use std::{
+    cell::Cell,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
 
+use anyhow::{anyhow, bail};
 use ascii_forge::prelude::*;
 
 use crate::{
-    config::Config,
-    dir_items::dir_items,
-    entry::{Entry, EntryType},
+    config::{Config, SortConfig},
+    dir_items::dir_listing,
+    entry::Entry,
     events::ExplorerEvent,
+    file_ops,
+    filter::Filter,
+    git::GitStatus,
+    journal::{Journal, Operation},
+    paste::Register,
+    session::Session,
+    sort::SortMode,
+    trash::{Trash, TrashItem},
+    tree::Tree,
+    watcher::DirWatcher,
 };
 
+// How often the git status is read again, since changes in collapsed directories aren't watched.
+const GIT_REFRESH: Duration = Duration::from_secs(5);
+
 pub struct Explorer<'a> {
-    path: String,
-    entries: Vec<Entry>,
+    root: PathBuf,
+    tree: Tree,
     selected: usize,
     config: &'a Config,
     scroll: usize,
+
+    // The number of entry lines that fit on screen, as of the last render.
+    height: Cell<usize>,
+
+    watcher: DirWatcher,
+    filter: Filter,
+    sort: SortConfig,
+
+    // Marked entries, and the last entry toggled, which is where mark ranges start from.
+    marked: BTreeSet<PathBuf>,
+    mark_anchor: Option<PathBuf>,
+
+    register: Option<Register>,
+    journal: Journal,
+
+    git: GitStatus,
+    // The status currently being read, and whether it was already out of date when it started.
+    git_receiver: Option<Receiver<GitStatus>>,
+    git_outdated: bool,
+    git_started: Instant,
+
+    // The search query, whether entries that don't match it are hidden, and what was focused when it was started.
+    search: Option<String>,
+    search_filter: bool,
+    search_origin: Option<PathBuf>,
+    // The indices of the entries that are shown, which is all of them unless the search is filtering.
+    rows: Vec<usize>,
+
+    // A line in the focused file, like one picked from the grep results, for `$LINE`.
+    line: Option<(PathBuf, usize)>,
+
+    // The sizes of directories, worked out on background threads.
+    size_sender: Sender<(PathBuf, u64)>,
+    size_receiver: Receiver<(PathBuf, u64)>,
+
+    // What is left of the session being restored, while large directories are still being read.
+    restoring: Option<Session>,
 }
 
 impl<'a> Explorer<'a> {
-    pub fn new(path: &str, config: &'a Config) -> anyhow::Result<Self> {
-        Ok(Self {
-            path: path.to_string(),
-            entries: dir_items(Path::new(path), 1)?,
+    /// Opens the explorer at `path`, picking up where the last session there left off if `restore` is set.
+    pub fn new(path: &Path, config: &'a Config, restore: bool) -> anyhow::Result<Self> {
+        let root = path
+            .canonicalize()
+            .map_err(|e| anyhow!("failed to open {}: {e}", path.display()))?;
+        let mut watcher = DirWatcher::new()?;
+        watcher.watch(&root)?;
+
+        let session = match restore {
+            true => Session::load(&root).unwrap_or_default(),
+            false => Session::default(),
+        };
+        let mut filter = Filter::new(&root, &config.filter)?;
+        filter.enabled = session.filter_enabled.unwrap_or(filter.enabled);
+        filter.show_hidden = session.show_hidden.unwrap_or(filter.show_hidden);
+        let sort = session.sort.unwrap_or(config.sort);
+
+        // Watch the git directory too, so staging and commits update the status.
+        if config.git.enabled {
+            if let Some(git_dir) = GitStatus::git_dir(&root) {
+                watcher.watch(&git_dir)?;
+            }
+        }
+
+        let (size_sender, size_receiver) = channel();
+        let mut explorer = Self {
+            tree: Tree::new(&root, dir_listing(&root, 1, &filter, &sort)?),
+            root,
             selected: 0,
             config,
-            scroll: 0,
-        })
+            scroll: session.scroll,
+            height: Cell::new(0),
+            watcher,
+            filter,
+            sort,
+            marked: BTreeSet::new(),
+            mark_anchor: None,
+            register: None,
+            journal: Journal::new(),
+            git: GitStatus::default(),
+            git_receiver: None,
+            git_outdated: false,
+            git_started: Instant::now(),
+            search: None,
+            search_filter: false,
+            search_origin: None,
+            rows: vec![],
+            line: None,
+            size_sender,
+            size_receiver,
+            restoring: Some(session),
+        };
+        explorer.update_rows();
+        explorer.restore_session();
+        explorer.refresh_git();
+        Ok(explorer)
+    }
+
+    /// Expands what was expanded in the session, then selects what was selected.
+    /// Large directories are read in the background, so whatever isn't there yet is tried again
+    /// as they come in, until they have been read in full.
+    fn restore_session(&mut self) {
+        let Some(mut session) = self.restoring.take() else {
+            return;
+        };
+        let focused = self.tree.get(self.selected).map(|x| x.path.clone());
+
+        // Expanding a directory can bring in the next one, so this goes on until none are found.
+        let mut found = true;
+        while found {
+            found = false;
+            session
+                .expanded
+                .retain(|dir| match self.find(&self.root.join(dir)) {
+                    Some(idx) => {
+                        self.selected = idx;
+                        // Directories that can't be expanded anymore are left collapsed.
+                        let _ = self.expand();
+                        found = true;
+                        false
+                    }
+                    None => true,
+                });
+        }
+
+        // The scroll goes along with the selection, once it has been found.
+        match session
+            .selected
+            .as_ref()
+            .and_then(|x| self.find(&self.root.join(x)))
+        {
+            Some(idx) => {
+                self.selected = idx;
+                self.scroll = session.scroll;
+                session.selected = None;
+            }
+            None => self.selected = focused.and_then(|x| self.find(&x)).unwrap_or(0),
+        }
+        self.scroll = self.scroll.min(self.rows.len().saturating_sub(1));
+
+        let done = session.expanded.is_empty() && session.selected.is_none();
+        if !done && self.tree.loading() {
+            self.restoring = Some(session);
+        }
+    }
+
+    /// Returns the explorer's state, to be restored the next time it is opened in the same root.
+    pub fn session(&self) -> Session {
+        // Paths that can't be written to the session file are left out.
+        let relative = |path: &Path| {
+            path.strip_prefix(&self.root)
+                .ok()
+                .filter(|x| x.to_str().is_some())
+                .map(Path::to_path_buf)
+        };
+        Session {
+            expanded: self
+                .tree
+                .iter()
+                .filter(|x| x.expanded && x.is_dir())
+                .filter_map(|x| relative(&x.path))
+                .collect(),
+            selected: self.tree.get(self.selected).and_then(|x| relative(&x.path)),
+            scroll: self.scroll,
+            filter_enabled: Some(self.filter.enabled),
+            show_hidden: Some(self.filter.show_hidden),
+            sort: Some(self.sort),
+        }
+    }
+
+    /// Starts reading the git status in the background.
+    fn refresh_git(&mut self) {
+        if !self.config.git.enabled {
+            return;
+        }
+        if self.git_receiver.is_some() {
+            self.git_outdated = true;
+            return;
+        }
+        self.git_receiver = Some(GitStatus::spawn(&self.root));
+        self.git_started = Instant::now();
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn sort(&self) -> &SortConfig {
+        &self.sort
     }
 
     pub fn selection_valid(&mut self, mut idx: usize) -> bool {
         idx += self.scroll;
-        idx < self.entries.len()
+        idx < self.rows.len()
     }
 
     pub fn set_selected(&mut self, mut idx: usize) {
@@ -39,7 +242,14 @@ impl<'a> Explorer<'a> {
         }
         idx += self.scroll;
 
-        self.selected = idx;
+        self.selected = self.rows[idx];
+    }
+
+    /// Returns the row the selected entry is shown on, or the closest one if it is hidden.
+    fn row(&self) -> usize {
+        self.rows
+            .partition_point(|x| *x < self.selected)
+            .min(self.rows.len().saturating_sub(1))
     }
 
     pub fn handle_event(&mut self, event: ExplorerEvent) -> anyhow::Result<()> {
@@ -60,6 +270,33 @@ impl<'a> Explorer<'a> {
             }
             ExplorerEvent::Expand => self.expand()?,
             ExplorerEvent::Collapse => self.collapse(),
+            ExplorerEvent::ToggleMark => self.toggle_mark(),
+            ExplorerEvent::MarkRange => self.mark_range(),
+            ExplorerEvent::MarkAll => self.mark_all(),
+            ExplorerEvent::ClearMarks => self.marked.clear(),
+            ExplorerEvent::Yank => self.yank(false),
+            ExplorerEvent::Cut => self.yank(true),
+            ExplorerEvent::ToggleFilter => {
+                self.filter.enabled = !self.filter.enabled;
+                self.refresh()?;
+            }
+            ExplorerEvent::ToggleHidden => {
+                self.filter.show_hidden = !self.filter.show_hidden;
+                self.refresh()?;
+            }
+            ExplorerEvent::DirSize => self.dir_sizes(),
+            ExplorerEvent::CycleSort => {
+                self.sort.mode = self.sort.mode.next();
+                self.resort();
+            }
+            ExplorerEvent::ReverseSort => {
+                self.sort.reverse = !self.sort.reverse;
+                self.resort();
+            }
+            ExplorerEvent::ToggleDirsFirst => {
+                self.sort.dirs_first = !self.sort.dirs_first;
+                self.resort();
+            }
             _ => {
                 unimplemented!("event {event:?} should not be handled by explorer")
             }
@@ -76,59 +313,93 @@ impl<'a> Explorer<'a> {
     }
 
     pub fn scroll_down(&mut self, dist: usize) {
-        if self.scroll + dist >= self.entries.len() {
-            self.scroll = self.entries.len() - 1;
+        if self.scroll + dist >= self.rows.len() {
+            self.scroll = self.rows.len().saturating_sub(1);
         } else {
             self.scroll += dist;
         }
     }
 
     pub fn back(&mut self, dist: usize) {
-        if self.selected < dist {
-            self.selected = 0;
-        } else {
-            self.selected -= dist;
-        }
-        if self.selected < self.scroll {
-            self.scroll = self.selected;
-        }
+        let Some(&idx) = self.rows.get(self.row().saturating_sub(dist)) else {
+            return;
+        };
+        self.selected = idx;
+        self.ensure_visible();
     }
 
     pub fn advance(&mut self, dist: usize) {
-        self.selected += dist;
-        self.selected = self.selected.min(self.entries.len() - 1);
+        let row = (self.row() + dist).min(self.rows.len().saturating_sub(1));
+        let Some(&idx) = self.rows.get(row) else {
+            return;
+        };
+        self.selected = idx;
+        self.ensure_visible();
+    }
+
+    /// Scrolls the explorer so that the selected entry is on screen.
+    fn ensure_visible(&mut self) {
+        let height = self.height.get().max(1);
+        let row = self.row();
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + height {
+            self.scroll = row + 1 - height;
+        }
     }
 
     pub fn expand(&mut self) -> anyhow::Result<()> {
-        let new_entries = self.entries[self.selected].expand()?;
+        let Some(entry) = self.tree.get(self.selected) else {
+            return Ok(());
+        };
+        if entry.link.is_some() && entry.is_dir() && !entry.expanded {
+            if !self.filter.follow_dir_links {
+                bail!(
+                    "{} is a link, and links aren't followed",
+                    entry.path.display()
+                );
+            }
+            // Every directory the entry is shown inside of is expanded.
+            let ancestors = entry
+                .path
+                .ancestors()
+                .skip(1)
+                .take_while(|x| x.starts_with(&self.root));
+            if let Some(target) = entry.link_cycle(ancestors) {
+                bail!(
+                    "{} links to {} which it is already inside of",
+                    entry.path.display(),
+                    target.display()
+                );
+            }
+        }
 
-        if let Some(new_entries) = new_entries {
-            let mut v = self.entries.split_off(self.selected + 1);
-            self.entries.extend(new_entries);
-            self.entries.append(&mut v);
+        let entry = &mut self.tree[self.selected];
+        if let Some(listing) = entry.expand(&self.filter, &self.sort)? {
+            let path = entry.path.clone();
+            self.watcher.watch(&path)?;
+            self.tree.set_children(&path, listing);
+            self.update_rows();
         }
 
         Ok(())
     }
 
     pub fn collapse(&mut self) {
-        if !self.entries[self.selected].expanded {
+        let Some(entry) = self.tree.get(self.selected).filter(|x| x.expanded) else {
             return;
-        }
-        self.entries[self.selected].expanded = false;
+        };
+        let path = entry.path.clone();
+        self.tree[self.selected].expanded = false;
+        self.watcher.unwatch(&path);
 
-        let depth = self.entries[self.selected].depth;
-
-        while self.entries[self.selected + 1].depth > depth {
-            self.entries.remove(self.selected + 1);
-            if self.entries.len() <= self.selected + 1 {
-                break;
-            }
-        }
+        let removed = self.tree.remove_children(&path);
+        self.unwatch_subtree(&removed);
+        self.update_rows();
     }
 
     pub fn toggle(&mut self) -> anyhow::Result<()> {
-        match self.entries[self.selected].expanded {
+        match self.tree.get(self.selected).is_some_and(|x| x.expanded) {
             true => self.collapse(),
             false => self.expand()?,
         }
@@ -137,70 +408,717 @@ impl<'a> Explorer<'a> {
     }
 
     pub fn is_file(&self) -> bool {
-        match self.entries[self.selected].entry_type {
-            EntryType::Dir => false,
-            EntryType::File => true,
+        self.focused().is_some_and(|x| !x.is_dir())
+    }
+
+    /// Returns the focused entry, or nothing if the tree is empty or a filtering search hides everything.
+    fn focused(&self) -> Option<&Entry> {
+        match self.rows.is_empty() {
+            true => None,
+            false => self.tree.get(self.selected),
         }
     }
 
     pub fn find(&self, path: &Path) -> Option<usize> {
-        self.entries
-            .iter()
-            .enumerate()
-            .find(|x| x.1.path == path)
-            .map(|x| x.0)
+        self.tree.find(path)
     }
 
+    /// Returns the focused entry's path, or the root if nothing is focused.
     pub fn focused_path(&self) -> PathBuf {
-        self.entries[self.selected].path.clone()
+        self.focused().map_or(self.root.clone(), |x| x.path.clone())
     }
 
-    pub fn refresh(&mut self) -> anyhow::Result<()> {
-        let selected_path = self.entries[self.selected].path.clone();
-        let scroll = self.scroll;
+    /// Remembers a line in the focused file, until something else is focused.
+    pub fn set_line(&mut self, line: usize) {
+        self.line = Some((self.focused_path(), line));
+    }
+
+    /// Returns the line set in the focused file, or the first line.
+    pub fn focused_line(&self) -> usize {
+        match &self.line {
+            Some((path, line)) if *path == self.focused_path() => *line,
+            _ => 1,
+        }
+    }
 
-        let entries = self.entries.clone();
-        let expanded = entries
-            .into_iter()
-            .filter(|x| x.expanded)
-            .map(|x| x.path)
-            .collect::<Vec<PathBuf>>();
+    /// Returns the focused entry if it is a directory, otherwise the directory containing it.
+    pub fn focused_dir(&self) -> PathBuf {
+        let path = self.focused_path();
+        match self.is_file() {
+            true => path.parent().map(Path::to_path_buf).unwrap_or(path),
+            false => path,
+        }
+    }
 
-        *self = Self::new(&self.path, self.config)?;
+    /// Expands every directory leading up to the given path, then selects it.
+    pub fn reveal(&mut self, path: &Path) -> anyhow::Result<()> {
+        let path = file_ops::normalize(path)?;
+        path.symlink_metadata()?;
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            bail!("{} is outside of {}", path.display(), self.root.display());
+        };
 
-        for item in &expanded {
-            if let Some(idx) = self.find(item) {
-                self.set_selected(idx);
+        let mut current = self.root.clone();
+        for component in relative.parent().into_iter().flat_map(|x| x.components()) {
+            current.push(component);
+            if let Some(idx) = self.find(&current) {
+                self.selected = idx;
                 self.expand()?;
             }
         }
 
-        match self.find(&selected_path) {
-            Some(i) => {
-                self.set_selected(i);
-                self.scroll = scroll
+        if let Some(idx) = self.find(&path) {
+            self.selected = idx;
+            self.ensure_visible();
+        }
+        Ok(())
+    }
+
+    /// Re-reads the directories touched by a file operation, selecting the result if it is inside of the explorer.
+    fn refresh_and_reveal(&mut self, dirs: &[&Path], path: Option<&PathBuf>) -> anyhow::Result<()> {
+        let dirs = dirs
+            .iter()
+            .filter_map(|x| file_ops::normalize(x).ok())
+            .collect::<Vec<_>>();
+        self.refresh_dirs(&dirs)?;
+
+        let Some(path) = path else {
+            return Ok(());
+        };
+        let path = file_ops::normalize(path)?;
+        if path.starts_with(&self.root) {
+            self.reveal(&path)?;
+        }
+        Ok(())
+    }
+
+    pub fn create_file(&mut self, name: &str) -> anyhow::Result<PathBuf> {
+        let dir = self.focused_dir();
+        let path = dir.join(name);
+        file_ops::create_file(&path)?;
+        let created = self.journal.created(&path)?;
+        self.journal
+            .record(format!("Create {}", path.display()), vec![created]);
+        self.refresh_and_reveal(&[&dir, parent(&path)], Some(&path))?;
+        Ok(path)
+    }
+
+    pub fn create_dir(&mut self, name: &str) -> anyhow::Result<PathBuf> {
+        let dir = self.focused_dir();
+        let path = dir.join(name);
+        file_ops::create_dir(&path)?;
+        let created = self.journal.created(&path)?;
+        self.journal
+            .record(format!("Create {}", path.display()), vec![created]);
+        self.refresh_and_reveal(&[&dir, parent(&path)], Some(&path))?;
+        Ok(path)
+    }
+
+    pub fn rename(&mut self, name: &str) -> anyhow::Result<PathBuf> {
+        let Some(from) = self.focused().map(|x| x.path.clone()) else {
+            bail!("nothing is focused");
+        };
+        let to = from.with_file_name(name);
+        file_ops::move_path(&from, &to)?;
+        self.journal.record(
+            format!("Rename {} to {}", from.display(), to.display()),
+            vec![(from.clone(), to.clone())],
+        );
+        self.refresh_and_reveal(&[parent(&from), parent(&to)], Some(&to))?;
+        Ok(to)
+    }
+
+    pub fn copy(&mut self, to: &str) -> anyhow::Result<Vec<PathBuf>> {
+        self.transfer(to, false)
+    }
+
+    pub fn move_to(&mut self, to: &str) -> anyhow::Result<Vec<PathBuf>> {
+        self.transfer(to, true)
+    }
+
+    /// Copies or moves the selected entries to `to`, which has to be a directory when there are multiple.
+    fn transfer(&mut self, to: &str, cut: bool) -> anyhow::Result<Vec<PathBuf>> {
+        let sources = self.selected_roots();
+        if sources.is_empty() {
+            bail!("nothing is selected");
+        }
+        let to = self.focused_path().with_file_name(to);
+        if sources.len() > 1 && !to.is_dir() {
+            bail!("{} is not a directory", to.display());
+        }
+
+        let mut moves = vec![];
+        let mut res = Ok(());
+        for from in sources {
+            let dest = file_ops::destination(&from, to.clone());
+            res = match cut {
+                true => file_ops::move_path(&from, &dest).map(|_| (from, dest)),
+                false => file_ops::copy(&from, &dest).and_then(|_| self.journal.created(&dest)),
+            }
+            .map(|x| moves.push(x));
+            if res.is_err() {
+                break;
+            }
+        }
+
+        let done = moves.iter().map(|x| x.1.clone()).collect::<Vec<_>>();
+        let action = if cut { "Move" } else { "Copy" };
+        self.journal.record(
+            format!("{action} {} to {}", describe(&done), to.display()),
+            moves.clone(),
+        );
+
+        let dirs = moves
+            .iter()
+            .flat_map(|(from, to)| [parent(from), parent(to)])
+            .collect::<Vec<_>>();
+        self.refresh_and_reveal(&dirs, done.last())?;
+        res.map(|_| done)
+    }
+
+    /// Moves the selected entries into the journal's trash, so that they can be restored with undo.
+    pub fn delete(&mut self) -> anyhow::Result<Vec<PathBuf>> {
+        let paths = self.selected_roots();
+        if paths.is_empty() {
+            bail!("nothing is selected");
+        }
+        let mut moves = vec![];
+        let mut res = Ok(());
+        for path in &paths {
+            res = self.journal.trash(path).map(|x| moves.push(x));
+            if res.is_err() {
+                break;
+            }
+        }
+
+        let deleted = moves.iter().map(|x| x.0.clone()).collect::<Vec<_>>();
+        self.journal
+            .record(format!("Delete {}", describe(&deleted)), moves);
+
+        let dirs = deleted
+            .iter()
+            .map(|x| parent(x).to_path_buf())
+            .collect::<Vec<_>>();
+        self.refresh_dirs(&dirs)?;
+        self.ensure_visible();
+        res.map(|_| deleted)
+    }
+
+    /// Moves the selected entries into the user's trash, where other programs can restore them.
+    /// The info files are journaled as created, so undoing takes them back out of the trash.
+    pub fn trash(&mut self) -> anyhow::Result<Vec<PathBuf>> {
+        let paths = self.selected_roots();
+        if paths.is_empty() {
+            bail!("nothing is selected");
+        }
+        let trash = Trash::home()?;
+        let mut moves = vec![];
+        let mut trashed = vec![];
+        let mut res = Ok(());
+        for path in &paths {
+            res = trash.put(path).and_then(|(trash_move, info)| {
+                trashed.push(trash_move.0.clone());
+                moves.push(trash_move);
+                moves.push(self.journal.created(&info)?);
+                Ok(())
+            });
+            if res.is_err() {
+                break;
+            }
+        }
+
+        self.journal
+            .record(format!("Trash {}", describe(&trashed)), moves);
+
+        let dirs = trashed
+            .iter()
+            .map(|x| parent(x).to_path_buf())
+            .collect::<Vec<_>>();
+        self.refresh_dirs(&dirs)?;
+        self.ensure_visible();
+        res.map(|_| trashed)
+    }
+
+    /// Moves an item out of the trash back to where it was deleted from, and reveals it.
+    pub fn restore(&mut self, item: &TrashItem) -> anyhow::Result<PathBuf> {
+        item.restore()?;
+        let mut moves = vec![(item.path.clone(), item.original.clone())];
+        moves.push(self.journal.trash(&item.info)?);
+        self.journal
+            .record(format!("Restore {}", item.original.display()), moves);
+
+        self.refresh_and_reveal(&[parent(&item.original)], Some(&item.original))?;
+        Ok(item.original.clone())
+    }
+
+    /// Records an operation done outside of the explorer, like a paste or a shell command.
+    pub fn record(&mut self, description: String, moves: Vec<(PathBuf, PathBuf)>) {
+        self.journal.record(description, moves);
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+
+    pub fn undo(&mut self) -> anyhow::Result<String> {
+        let op = self.journal.undo()?;
+        self.refresh_touched(&op)?;
+        Ok(op.description)
+    }
+
+    pub fn redo(&mut self) -> anyhow::Result<String> {
+        let op = self.journal.redo()?;
+        self.refresh_touched(&op)?;
+        Ok(op.description)
+    }
+
+    /// Re-reads every directory an operation touched.
+    fn refresh_touched(&mut self, op: &Operation) -> anyhow::Result<()> {
+        let dirs = op
+            .paths()
+            .map(|x| parent(x).to_path_buf())
+            .collect::<Vec<_>>();
+        self.refresh_dirs(&dirs)
+    }
+
+    /// Describes the journal in up to `lines` lines, newest first.
+    pub fn journal_summary(&self, count: usize) -> String {
+        self.journal.summary(count)
+    }
+
+    /// Returns the marked entries, or the focused entry if nothing is marked.
+    /// This is empty when nothing is marked or focused.
+    pub fn selected_paths(&self) -> Vec<PathBuf> {
+        match self.marked.is_empty() {
+            true => self.focused().map(|x| x.path.clone()).into_iter().collect(),
+            false => self.marked.iter().cloned().collect(),
+        }
+    }
+
+    /// Returns the selected entries, leaving out any inside of another selected directory,
+    /// since operating on the directory already takes care of them.
+    fn selected_roots(&self) -> Vec<PathBuf> {
+        let paths = self.selected_paths();
+        paths
+            .iter()
+            .filter(|x| !paths.iter().any(|y| y != *x && x.starts_with(y)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn toggle_mark(&mut self) {
+        let Some(path) = self.focused().map(|x| x.path.clone()) else {
+            return;
+        };
+        if !self.marked.remove(&path) {
+            self.marked.insert(path.clone());
+        }
+        self.mark_anchor = Some(path);
+    }
+
+    /// Marks every entry between the last toggled entry and the focused one.
+    pub fn mark_range(&mut self) {
+        let anchor = self
+            .mark_anchor
+            .as_ref()
+            .and_then(|x| self.find(x))
+            .unwrap_or(self.selected);
+        let (start, end) = (anchor.min(self.selected), anchor.max(self.selected));
+        // Entries hidden by the search are left out.
+        self.marked.extend(
+            self.rows
+                .iter()
+                .filter(|x| (start..=end).contains(*x))
+                .map(|x| self.tree[*x].path.clone()),
+        );
+    }
+
+    /// Stores the selected entries in the register, to be pasted later.
+    pub fn yank(&mut self, cut: bool) {
+        let paths = self.selected_roots();
+        if paths.is_empty() {
+            return;
+        }
+        self.register = Some(Register { paths, cut });
+        self.marked.clear();
+    }
+
+    pub fn register(&self) -> Option<&Register> {
+        self.register.as_ref()
+    }
+
+    pub fn clear_register(&mut self) {
+        self.register = None;
+    }
+
+    /// Marks every entry in the same directory as the focused one.
+    pub fn mark_all(&mut self) {
+        let focused = self.focused_path();
+        let dir = parent(&focused);
+        self.marked.extend(self.tree.children(dir).iter().cloned());
+    }
+
+    /// Starts working out how big each selected directory is, counting everything inside of it.
+    pub fn dir_sizes(&mut self) {
+        for path in self.selected_paths().into_iter().filter(|x| x.is_dir()) {
+            let sender = self.size_sender.clone();
+            thread::spawn(move || {
+                let size = ignore::WalkBuilder::new(&path)
+                    .standard_filters(false)
+                    .build()
+                    .flatten()
+                    .filter_map(|x| x.metadata().ok())
+                    .filter(|x| x.is_file())
+                    .map(|x| x.len())
+                    .sum();
+                let _ = sender.send((path, size));
+            });
+        }
+    }
+
+    /// Applies any changes the watcher has seen since the last update.
+    pub fn update(&mut self) -> anyhow::Result<()> {
+        let mut sized = false;
+        for (path, size) in self.size_receiver.try_iter() {
+            if let Some(entry) = self.tree.entry_mut(&path) {
+                entry.dir_size = Some(size);
+                sized = true;
+            }
+        }
+        // Directories are sorted by the size of what's inside of them, once that's known.
+        if sized && self.sort.mode == SortMode::Size {
+            self.resort();
+        }
+
+        // Add whatever has been read of large directories, and drop what a refresh found gone,
+        // keeping the same entry selected.
+        let focused = self.tree.get(self.selected).map(|x| x.path.clone());
+        if let Some(removed) = self.tree.update(&self.sort) {
+            self.unwatch_subtree(&removed);
+            if !removed.is_empty() {
+                self.marked.retain(|x| x.exists());
+            }
+            self.update_rows();
+            self.reselect(focused);
+            self.restore_session();
+        }
+
+        if let Some(receiver) = &self.git_receiver {
+            match receiver.try_recv() {
+                Ok(status) => {
+                    self.git = status;
+                    self.git_receiver = None;
+                }
+                Err(TryRecvError::Disconnected) => self.git_receiver = None,
+                Err(TryRecvError::Empty) => {}
+            }
+            if self.git_receiver.is_none() && std::mem::take(&mut self.git_outdated) {
+                self.refresh_git();
+            }
+        }
+        if self.git_receiver.is_none() && self.git_started.elapsed() >= GIT_REFRESH {
+            self.refresh_git();
+        }
+
+        let mut dirs = self.watcher.changed_dirs().into_iter().collect::<Vec<_>>();
+        if dirs.is_empty() {
+            return Ok(());
+        }
+        self.refresh_git();
+
+        // Refresh parents first, so directories that were removed with them are skipped.
+        dirs.sort_by_key(|x| x.components().count());
+        self.refresh_dirs(&dirs)
+    }
+
+    /// Re-reads the root and every expanded directory.
+    pub fn refresh(&mut self) -> anyhow::Result<()> {
+        let mut dirs = vec![self.root.clone()];
+        dirs.extend(
+            self.tree
+                .iter()
+                .filter(|x| x.expanded)
+                .map(|x| x.path.clone()),
+        );
+        self.refresh_dirs(&dirs)
+    }
+
+    /// Re-reads the given directories, keeping the selection on the same entry if it still exists.
+    fn refresh_dirs(&mut self, dirs: &[PathBuf]) -> anyhow::Result<()> {
+        let selected_path = self.tree.get(self.selected).map(|x| x.path.clone());
+
+        // A directory that can't be read doesn't stop the rest from being refreshed.
+        let mut result = Ok(());
+        for dir in dirs {
+            if let Err(e) = self.refresh_dir(dir) {
+                result = result.and(Err(e));
             }
-            None => self.set_selected(0),
         }
+        self.marked.retain(|x| x.exists());
+        self.update_rows();
+        self.reselect(selected_path);
+
+        result
+    }
+
+    /// Selects the path again after the tree changed, or whatever took its place if it's gone,
+    /// keeping the scroll within the rows that are left.
+    fn reselect(&mut self, path: Option<PathBuf>) {
+        let last = self.tree.len().saturating_sub(1);
+        self.selected = match path.and_then(|x| self.find(&x)) {
+            Some(idx) => idx,
+            None => self.selected.min(last),
+        };
+        self.select_shown();
+        self.scroll = self.scroll.min(self.rows.len().saturating_sub(1));
+        self.ensure_visible();
+    }
 
+    /// Re-reads a single directory, patching its children in place.
+    /// Children that still exist keep their expanded subtrees.
+    fn refresh_dir(&mut self, dir: &Path) -> anyhow::Result<()> {
+        let depth = if dir == self.root {
+            1
+        } else {
+            match self.tree.entry_mut(dir) {
+                Some(entry) if entry.expanded => entry.depth + 1,
+                _ => return Ok(()),
+            }
+        };
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        let result = dir_listing(dir, depth, &self.filter, &self.sort);
+        if let Some(entry) = self.tree.entry_mut(dir) {
+            entry.error = result.as_ref().err().map(|x| x.to_string());
+        }
+
+        let removed = self.tree.replace_children(dir, result?, &self.sort);
+        self.unwatch_subtree(&removed);
+        Ok(())
+    }
+
+    /// Sorts every listed directory again, keeping expanded directories expanded.
+    fn resort(&mut self) {
+        let focused = self.focused_path();
+        self.tree.resort(&self.sort);
+        self.update_rows();
+
+        if let Some(idx) = self.find(&focused) {
+            self.selected = idx;
+        }
+        self.ensure_visible();
+    }
+
+    /// Works out which entries are shown, hiding those that don't match a filtering search.
+    /// Directories stay shown when anything inside of them matches.
+    /// The scroll is kept within the rows that are left.
+    fn update_rows(&mut self) {
+        self.rows = self.shown_rows();
+        self.scroll = self.scroll.min(self.rows.len().saturating_sub(1));
+    }
+
+    fn shown_rows(&self) -> Vec<usize> {
+        let Some(query) = self.search.as_ref().filter(|_| self.search_filter) else {
+            return (0..self.tree.len()).collect();
+        };
+
+        let mut shown = vec![false; self.tree.len()];
+        let mut ancestors: Vec<(usize, usize)> = vec![];
+        for (i, entry) in self.tree.iter().enumerate() {
+            while ancestors.last().is_some_and(|x| x.1 >= entry.depth) {
+                ancestors.pop();
+            }
+            if entry.search_match(query).is_some() {
+                shown[i] = true;
+                for (idx, _) in ancestors.iter().rev() {
+                    if std::mem::replace(&mut shown[*idx], true) {
+                        break;
+                    }
+                }
+            }
+            ancestors.push((i, entry.depth));
+        }
+        (0..self.tree.len()).filter(|x| shown[*x]).collect()
+    }
+
+    /// Moves the selection onto a shown entry, if it was hidden.
+    fn select_shown(&mut self) {
+        if let Some(&idx) = self.rows.get(self.row()) {
+            self.selected = idx;
+        }
+    }
+
+    /// Remembers what is focused, so that cancelling the search can go back to it.
+    pub fn begin_search(&mut self) {
+        self.search_origin = Some(self.focused_path());
+    }
+
+    /// Updates the query as it is typed, selecting the first match from where the search began.
+    pub fn set_search(&mut self, query: &str, filter: bool) {
+        self.search = (!query.is_empty()).then(|| query.to_string());
+        self.search_filter = filter && self.search.is_some();
+        self.update_rows();
+
+        if let Some(idx) = self.search_origin.as_ref().and_then(|x| self.find(x)) {
+            self.selected = idx;
+        }
+        if !self.select_match(true, false) {
+            self.select_shown();
+        }
+        self.ensure_visible();
+    }
+
+    /// Keeps the query, so it stays highlighted and can be stepped through.
+    pub fn finish_search(&mut self) {
+        self.search_origin = None;
+    }
+
+    /// Clears the query and goes back to what was focused before the search.
+    pub fn cancel_search(&mut self) {
+        let origin = self.search_origin.take();
+        self.clear_search();
+        if let Some(idx) = origin.and_then(|x| self.find(&x)) {
+            self.selected = idx;
+            self.ensure_visible();
+        }
+    }
+
+    /// Clears the query, showing every entry again.
+    pub fn clear_search(&mut self) {
+        self.search = None;
+        self.search_filter = false;
+        self.update_rows();
+        self.ensure_visible();
+    }
+
+    /// Selects the next (or previous) shown entry that matches the query.
+    pub fn next_match(&mut self, forward: bool) -> anyhow::Result<()> {
+        let Some(query) = self.search.clone() else {
+            bail!("nothing has been searched for");
+        };
+        if !self.select_match(forward, true) {
+            bail!("nothing matches {query}");
+        }
+        self.ensure_visible();
         Ok(())
     }
+
+    /// Selects the closest match, wrapping around, and starting after the selection if `skip` is set.
+    fn select_match(&mut self, forward: bool, skip: bool) -> bool {
+        let Some(query) = &self.search else {
+            return false;
+        };
+        let (row, len) = (self.row(), self.rows.len());
+        for step in skip as usize..len + skip as usize {
+            let row = match forward {
+                true => (row + step) % len,
+                false => (row + len - step % len) % len,
+            };
+            let idx = self.rows[row];
+            if self.tree[idx].search_match(query).is_some() {
+                self.selected = idx;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns how many shown entries match the query.
+    pub fn match_count(&self) -> usize {
+        let Some(query) = &self.search else {
+            return 0;
+        };
+        self.rows
+            .iter()
+            .filter(|x| self.tree[**x].search_match(query).is_some())
+            .count()
+    }
+
+    fn unwatch_subtree(&mut self, entries: &[Entry]) {
+        for entry in entries.iter().filter(|x| x.expanded) {
+            self.watcher.unwatch(&entry.path);
+        }
+    }
+}
+
+fn parent(path: &Path) -> &Path {
+    path.parent().unwrap_or(path)
+}
+
+/// Describes a list of paths for the journal.
+fn describe(paths: &[PathBuf]) -> String {
+    match paths {
+        [path] => path.display().to_string(),
+        _ => format!("{} entries", paths.len()),
+    }
 }
 
 impl<'a> Render for Explorer<'a> {
     fn render(&self, mut loc: Vec2, buffer: &mut Buffer) -> Vec2 {
         let start_line = self.scroll;
-        let max_lines = (start_line + buffer.size().y as usize - 7).min(self.entries.len());
+        self.height
+            .set((buffer.size().y as usize).saturating_sub(7));
 
-        render!(buffer, loc => [ "./" ]);
+        let end = match self.search.as_ref().filter(|_| self.search_filter) {
+            Some(query) => {
+                render!(buffer, loc => [ "./", format!("  filtered by {query}").dark_grey() ])
+            }
+            None => render!(buffer, loc => [ "./" ]),
+        };
+        if self.tree.loading() {
+            render!(buffer, end => [ "  reading…".dark_grey() ]);
+        }
 
-        for i in start_line..max_lines {
+        for &i in self.rows.iter().skip(start_line).take(self.height.get()) {
             loc.y += 1;
 
-            let entry = &self.entries[i];
+            let entry = &self.tree[i];
 
-            entry.render(loc, buffer, i == self.selected, self.config);
+            entry.render(
+                loc,
+                buffer,
+                i == self.selected,
+                self.marked.contains(&entry.path),
+                self.git.get(&entry.path),
+                self.search.as_deref(),
+                self.config,
+            );
         }
         loc
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ascii_forge::prelude::*;
+
+    use super::Explorer;
+    use crate::config::Config;
+
+    #[test]
+    fn collapsing_after_scrolling_keeps_the_scroll_in_range() {
+        let dir = std::env::temp_dir().join(format!("idex-explorer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("big")).unwrap();
+        for i in 0..50 {
+            fs::write(dir.join(format!("big/{i}")), "").unwrap();
+        }
+
+        let config = Config::default();
+        let mut explorer = Explorer::new(&dir, &config, false).unwrap();
+        explorer.expand().unwrap();
+        explorer.scroll_down(45);
+        explorer.collapse();
+        let scroll = explorer.scroll;
+        explorer.render(vec2(0, 0), &mut Buffer::new(vec2(40, 20)));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(scroll, 0);
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+
+/// Creates an empty file, failing if anything already exists at the path.
+pub fn create_file(path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| anyhow!("failed to create {}: {e}", path.display()))?;
+    Ok(())
+}
+
+/// Creates a directory, along with any missing parents.
+pub fn create_dir(path: &Path) -> anyhow::Result<()> {
+    if path.symlink_metadata().is_ok() {
+        bail!("{} already exists", path.display());
+    }
+    fs::create_dir_all(path).map_err(|e| anyhow!("failed to create {}: {e}", path.display()))
+}
+
+/// Removes a file or a directory and everything inside of it.
+pub fn remove(path: &Path) -> anyhow::Result<()> {
+    let res = match path.symlink_metadata()?.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    };
+    res.map_err(|e| anyhow!("failed to delete {}: {e}", path.display()))
+}
+
+/// Copies a file or a directory recursively to `to`.
+pub fn copy(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if to.symlink_metadata().is_ok() {
+        bail!("{} already exists", to.display());
+    }
+    copy_with_progress(from, to, &mut |_| {})
+}
+
+/// Copies recursively like `copy`, calling `on_copy` with every path as it is copied.
+pub fn copy_with_progress(
+    from: &Path,
+    to: &Path,
+    on_copy: &mut dyn FnMut(&Path),
+) -> anyhow::Result<()> {
+    if to.starts_with(from) {
+        bail!("can't copy {} into itself", from.display());
+    }
+    copy_recursive(from, to, on_copy).map_err(|e| anyhow!("failed to copy {}: {e}", from.display()))
+}
+
+fn copy_recursive(from: &Path, to: &Path, on_copy: &mut dyn FnMut(&Path)) -> std::io::Result<()> {
+    on_copy(from);
+    let meta = from.symlink_metadata()?;
+    if meta.is_dir() {
+        fs::create_dir(to)?;
+        for entry in from.read_dir()? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()), on_copy)?;
+        }
+        fs::set_permissions(to, meta.permissions())?;
+    } else if meta.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Moves a file or directory, falling back to copy and delete when moving across file systems.
+pub fn move_path(from: &Path, to: &Path) -> anyhow::Result<()> {
+    // A link counts as being there even if it's broken.
+    if to.symlink_metadata().is_ok() {
+        bail!("{} already exists", to.display());
+    }
+    if to.starts_with(from) {
+        bail!("can't move {} into itself", from.display());
+    }
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            copy(from, to)?;
+            remove(from)
+        }
+        Err(e) => Err(anyhow!("failed to move {}: {e}", from.display())),
+    }
+}
+
+/// Resolves the destination of a copy or move.
+/// If `to` is an existing directory, the item is placed inside of it under its own name.
+pub fn destination(from: &Path, to: PathBuf) -> PathBuf {
+    match (to.is_dir(), from.file_name()) {
+        (true, Some(name)) => to.join(name),
+        _ => to,
+    }
+}
+
+/// Returns a path next to `path` that isn't taken yet, like `name (copy).txt`.
+/// Paths in `claimed` count as taken, even though nothing is there yet.
+pub fn unique_name(path: &Path, claimed: &HashSet<PathBuf>) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|x| format!(".{}", x.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut candidate = path.with_file_name(format!("{stem} (copy){ext}"));
+    let mut n = 2;
+    while candidate.symlink_metadata().is_ok() || claimed.contains(&candidate) {
+        candidate = path.with_file_name(format!("{stem} (copy {n}){ext}"));
+        n += 1;
+    }
+    candidate
+}
+
+/// Makes a path absolute and resolves `.` and `..` without following links,
+/// so paths through a linked directory stay inside of it.
+pub fn normalize(path: &Path) -> anyhow::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Counts the files and directories under `path`, including itself.
+pub fn count(path: &Path) -> usize {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => {
+            1 + path
+                .read_dir()
+                .map(|x| x.flatten().map(|x| count(&x.path())).sum())
+                .unwrap_or(0)
+        }
+        _ => 1,
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::{Path, PathBuf};
+
+use globset::{GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+use crate::config::FilterConfig;
+
+/// Decides which entries are listed, based off of ignore files, dotfiles, and the user's exclude globs.
+#[derive(Clone)]
+pub struct Filter {
+    root: PathBuf,
+    pub enabled: bool,
+    pub show_hidden: bool,
+    git_ignore: bool,
+    pub follow_dir_links: bool,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    pub fn new(root: &Path, config: &FilterConfig) -> anyhow::Result<Self> {
+        let mut exclude = GlobSetBuilder::new();
+        for glob in &config.exclude {
+            exclude.add(glob.clone());
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            enabled: config.enabled,
+            show_hidden: config.show_hidden,
+            git_ignore: config.git_ignore,
+            follow_dir_links: config.follow_dir_links,
+            exclude: exclude.build()?,
+        })
+    }
+
+    /// Creates a walker over `dir` that skips everything this filter hides.
+    /// Hidden entries are up to `show_hidden` even while the rest of the filter is disabled,
+    /// so toggling them always does something.
+    pub fn walker(&self, dir: &Path) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(dir);
+        builder
+            .standard_filters(false)
+            .hidden(!self.show_hidden)
+            .follow_links(self.follow_dir_links);
+        if !self.enabled {
+            return builder;
+        }
+
+        builder
+            .parents(self.git_ignore)
+            .ignore(self.git_ignore)
+            .git_ignore(self.git_ignore)
+            .git_global(self.git_ignore)
+            .git_exclude(self.git_ignore)
+            .require_git(false);
+
+        let root = self.root.clone();
+        let exclude = self.exclude.clone();
+        builder.filter_entry(move |entry| {
+            let path = entry.path();
+            let relative = path.strip_prefix(&root).unwrap_or(path);
+            !(exclude.is_match(entry.file_name()) || exclude.is_match(relative))
+        });
+        builder
+    }
+}
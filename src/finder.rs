@@ -0,0 +1,216 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use ascii_forge::prelude::*;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
+use crate::{
+    filter::Filter,
+    input::{Input, InputEvent},
+};
+
+// How long ranking can take per update, so typing stays responsive in huge trees,
+// and how many paths are scored between checking the time.
+const RANK_BUDGET: Duration = Duration::from_millis(15);
+const RANK_CHUNK: usize = 1000;
+
+pub enum FinderEvent {
+    Cancel,
+    Accept(PathBuf),
+}
+
+/// A fuzzy "go to file" prompt over every file under the explorer's root.
+/// The tree is walked on a background thread, and matches are ranked as paths come in.
+/// Ranking only takes so long per update, carrying on with the rest in the next one.
+#[derive(Default)]
+pub struct Finder {
+    root: PathBuf,
+    input: Input,
+
+    // Paths relative to the root, in the order they were found.
+    paths: Vec<String>,
+    receiver: Option<Receiver<String>>,
+
+    // Indices into paths along with their scores, best match first.
+    matches: Vec<(i64, usize)>,
+    selected: usize,
+    // The query the matches are for, and the paths that still have to be scored against it.
+    query: String,
+    unscored: Vec<usize>,
+
+    matcher: SkimMatcherV2,
+}
+
+impl Finder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn active(&self) -> bool {
+        self.input.active()
+    }
+
+    pub fn open(&mut self, root: &Path, filter: &Filter) {
+        let (sender, receiver) = channel();
+        let walker = filter.walker(root).build();
+        let walk_root = root.to_path_buf();
+        thread::spawn(move || {
+            for entry in walker.flatten().filter(|x| x.depth() > 0) {
+                let relative = entry
+                    .path()
+                    .strip_prefix(&walk_root)
+                    .unwrap_or(entry.path());
+                // The finder has been closed once the receiver is dropped.
+                if sender
+                    .send(relative.to_string_lossy().into_owned())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        *self = Self {
+            root: root.to_path_buf(),
+            receiver: Some(receiver),
+            ..Self::default()
+        };
+        self.input.set_active(true);
+    }
+
+    fn close(&mut self) {
+        // Dropping the receiver stops the walker.
+        *self = Self::default();
+    }
+
+    pub fn text(&self) -> &str {
+        self.input.get_text()
+    }
+
+    /// Returns the number of matches, and the number of paths found so far.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.matches.len(), self.paths.len())
+    }
+
+    /// Returns whether some paths are still to be ranked, so the caller can update again sooner.
+    pub fn pending(&self) -> bool {
+        !self.unscored.is_empty()
+    }
+
+    /// Collects any paths the walker has found since the last update, and ranks more of them.
+    pub fn update(&mut self) {
+        if let Some(receiver) = &self.receiver {
+            let found = self.paths.len();
+            self.paths.extend(receiver.try_iter());
+            self.unscored.extend(found..self.paths.len());
+        }
+        self.rank();
+    }
+
+    /// Starts ranking again after the query changed. When it was only added to, nothing that
+    /// didn't match before can match now, so just the matches are scored again.
+    fn set_query(&mut self) {
+        let query = self.input.get_text();
+        if query == self.query {
+            return;
+        }
+        if query.starts_with(&self.query) {
+            self.unscored.extend(self.matches.drain(..).map(|x| x.1));
+        } else {
+            self.matches.clear();
+            self.unscored = (0..self.paths.len()).collect();
+        }
+        self.query = query.to_string();
+        self.rank();
+    }
+
+    /// Scores paths against the query for as long as the budget allows, merging them into the matches.
+    fn rank(&mut self) {
+        let started = Instant::now();
+        let mut scored = vec![];
+        while started.elapsed() < RANK_BUDGET {
+            let chunk = self
+                .unscored
+                .split_off(self.unscored.len().saturating_sub(RANK_CHUNK));
+            if chunk.is_empty() {
+                break;
+            }
+            scored.extend(chunk.into_iter().filter_map(|i| {
+                let score = self.matcher.fuzzy_match(&self.paths[i], &self.query)?;
+                Some((score, i))
+            }));
+        }
+        if scored.is_empty() {
+            return;
+        }
+
+        // The matches are already sorted, so this is mostly merging the new ones in.
+        self.matches.extend(scored);
+        let paths = &self.paths;
+        self.matches
+            .sort_by(|a, b| b.0.cmp(&a.0).then(paths[a.1].len().cmp(&paths[b.1].len())));
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+    }
+
+    pub fn handle(&mut self, k: KeyEvent) -> Option<FinderEvent> {
+        if !(k.kind == KeyEventKind::Press || k.kind == KeyEventKind::Repeat) {
+            return None;
+        }
+
+        match k.code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1))
+            }
+            _ => match self.input.event(k) {
+                Some(InputEvent::Cancel) => {
+                    self.close();
+                    return Some(FinderEvent::Cancel);
+                }
+                Some(InputEvent::Accept) => {
+                    let path = self
+                        .matches
+                        .get(self.selected)
+                        .map(|x| self.root.join(&self.paths[x.1]));
+                    self.close();
+                    return Some(path.map_or(FinderEvent::Cancel, FinderEvent::Accept));
+                }
+                None => self.set_query(),
+            },
+        }
+        None
+    }
+}
+
+impl Render for Finder {
+    fn render(&self, mut loc: Vec2, buffer: &mut Buffer) -> Vec2 {
+        let max_lines = (buffer.size().y as usize)
+            .saturating_sub(7)
+            .min(self.matches.len());
+        let start_line = (self.selected + 1).saturating_sub(max_lines);
+
+        render!(buffer, loc => [ "./" ]);
+
+        for (i, idx) in self
+            .matches
+            .iter()
+            .enumerate()
+            .skip(start_line)
+            .take(max_lines)
+        {
+            loc.y += 1;
+
+            let path = &self.paths[idx.1];
+            if i == self.selected {
+                render!(buffer, loc => [ " > ", path.as_str().green(), " <" ]);
+            } else {
+                render!(buffer, loc => [ "   ", path ]);
+            }
+        }
+        loc
+    }
+}
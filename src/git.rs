@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::mpsc::{channel, Receiver},
+    thread,
+};
+
+/// The git status of an entry, ordered from least to most important.
+/// Directories show the most important status of anything inside of them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitState {
+    Ignored,
+    Untracked,
+    Staged,
+    Modified,
+    Conflicted,
+}
+
+impl GitState {
+    fn parse(code: &str) -> Self {
+        let (x, y) = code.split_at(1);
+        match (x, y) {
+            ("?", "?") => Self::Untracked,
+            ("!", "!") => Self::Ignored,
+            ("U", _) | (_, "U") | ("A", "A") | ("D", "D") => Self::Conflicted,
+            (_, " ") => Self::Staged,
+            (_, _) => Self::Modified,
+        }
+    }
+}
+
+/// A snapshot of `git status` for the repository containing the explorer's root.
+#[derive(Default)]
+pub struct GitStatus {
+    top_level: PathBuf,
+    states: HashMap<PathBuf, GitState>,
+    // The most important state inside of each directory.
+    dirty: HashMap<PathBuf, GitState>,
+}
+
+impl GitStatus {
+    /// Returns the `.git` directory of the repository containing `root`, if there is one.
+    pub fn git_dir(root: &Path) -> Option<PathBuf> {
+        git(root, &["rev-parse", "--absolute-git-dir"]).map(PathBuf::from)
+    }
+
+    /// Reads the status of the repository containing `root` on a background thread.
+    pub fn spawn(root: &Path) -> Receiver<GitStatus> {
+        let (sender, receiver) = channel();
+        let root = root.to_path_buf();
+        thread::spawn(move || sender.send(Self::read(&root)));
+        receiver
+    }
+
+    fn read(root: &Path) -> Self {
+        let Some(top_level) = git(root, &["rev-parse", "--show-toplevel"]).map(PathBuf::from)
+        else {
+            return Self::default();
+        };
+        // Canonicalize so that paths line up with the explorer's entries.
+        let top_level = top_level.canonicalize().unwrap_or(top_level);
+
+        let output = git(
+            root,
+            &[
+                "--no-optional-locks",
+                "status",
+                "--porcelain=v1",
+                "-z",
+                "--ignored=matching",
+            ],
+        );
+        Self::parse(top_level, output.as_deref().unwrap_or_default())
+    }
+
+    /// Reads the output of `git status --porcelain=v1 -z` for the repository at `top_level`.
+    fn parse(top_level: PathBuf, output: &str) -> Self {
+        let mut status = Self {
+            top_level,
+            ..Self::default()
+        };
+        let mut records = output.split('\0');
+        while let Some(record) = records.next() {
+            if record.len() < 4 {
+                continue;
+            }
+            let (code, path) = record.split_at(3);
+            // Renames and copies are followed by the original path, which isn't shown.
+            if code.starts_with('R') || code.starts_with('C') {
+                records.next();
+            }
+            status.insert(path.trim_end_matches('/'), GitState::parse(&code[..2]));
+        }
+        status
+    }
+
+    fn insert(&mut self, path: &str, state: GitState) {
+        let path = self.top_level.join(path);
+        if state != GitState::Ignored {
+            // Mark every parent as dirty, up to the top of the repository.
+            for parent in path.ancestors().skip(1) {
+                if !parent.starts_with(&self.top_level) {
+                    break;
+                }
+                let dirty = self.dirty.entry(parent.to_path_buf()).or_insert(state);
+                *dirty = (*dirty).max(state);
+            }
+        }
+        let entry = self.states.entry(path).or_insert(state);
+        *entry = (*entry).max(state);
+    }
+
+    /// Returns the state of the path, inheriting from untracked or ignored directories.
+    pub fn get(&self, path: &Path) -> Option<GitState> {
+        let state = self.states.get(path).max(self.dirty.get(path));
+        if state.is_some() {
+            return state.copied();
+        }
+        path.ancestors()
+            .skip(1)
+            .take_while(|x| x.starts_with(&self.top_level))
+            .filter_map(|x| self.states.get(x))
+            .find(|x| matches!(x, GitState::Untracked | GitState::Ignored))
+            .copied()
+    }
+}
+
+/// Runs a git command in `dir`, returning its output if it succeeded.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    Some(text.trim_end_matches('\n').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{GitState, GitStatus};
+
+    fn parse(output: &str) -> GitStatus {
+        GitStatus::parse(PathBuf::from("/repo"), output)
+    }
+
+    #[test]
+    fn codes_are_parsed() {
+        let status =
+            parse(" M modified\0M  staged\0MM both\0?? new\0!! ignored\0UU conflict\0AA added\0");
+        let get = |path: &str| status.get(&Path::new("/repo").join(path));
+        assert_eq!(get("modified"), Some(GitState::Modified));
+        assert_eq!(get("staged"), Some(GitState::Staged));
+        assert_eq!(get("both"), Some(GitState::Modified));
+        assert_eq!(get("new"), Some(GitState::Untracked));
+        assert_eq!(get("ignored"), Some(GitState::Ignored));
+        assert_eq!(get("conflict"), Some(GitState::Conflicted));
+        assert_eq!(get("added"), Some(GitState::Conflicted));
+        assert_eq!(get("clean"), None);
+    }
+
+    #[test]
+    fn renames_skip_the_original_path() {
+        let status = parse("R  new name\0old name\0 M other\0");
+        assert_eq!(
+            status.get(Path::new("/repo/new name")),
+            Some(GitState::Staged)
+        );
+        assert_eq!(status.get(Path::new("/repo/old name")), None);
+        assert_eq!(
+            status.get(Path::new("/repo/other")),
+            Some(GitState::Modified)
+        );
+    }
+
+    #[test]
+    fn directories_show_the_most_important_state_inside() {
+        let status = parse("?? a/b/new\0 M a/changed\0!! a/target/\0");
+        assert_eq!(status.get(Path::new("/repo/a")), Some(GitState::Modified));
+        assert_eq!(
+            status.get(Path::new("/repo/a/b")),
+            Some(GitState::Untracked)
+        );
+        // Ignored entries don't make their parents dirty.
+        assert_eq!(status.get(Path::new("/repo")), Some(GitState::Modified));
+    }
+
+    #[test]
+    fn untracked_and_ignored_directories_are_inherited() {
+        let status = parse("?? new/\0!! target/\0");
+        assert_eq!(
+            status.get(Path::new("/repo/new/deep/file")),
+            Some(GitState::Untracked)
+        );
+        assert_eq!(
+            status.get(Path::new("/repo/target/debug")),
+            Some(GitState::Ignored)
+        );
+        assert_eq!(status.get(Path::new("/elsewhere")), None);
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+};
+
+use anyhow::anyhow;
+use ascii_forge::prelude::*;
+use regex_automata::{meta::Regex, util::syntax};
+
+use crate::{config::Config, filter::Filter, output};
+
+// How much of a line is kept before and after the match.
+const CONTEXT_BEFORE: usize = 40;
+const CONTEXT_AFTER: usize = 200;
+// How much of a line is searched, the rest of a longer one, like in minified files, is skipped.
+const MAX_LINE: u64 = 64 * 1024;
+
+/// A matching line, split around the first match so it can be highlighted.
+pub struct GrepMatch {
+    pub line: usize,
+    before: String,
+    matched: String,
+    after: String,
+}
+
+/// Every match in a file, in the order of its lines.
+pub struct GrepFile {
+    pub path: PathBuf,
+    pub matches: Vec<GrepMatch>,
+}
+
+// A line of the results, either a file's path or one of its matches.
+enum Row {
+    File(usize),
+    Match(usize),
+}
+
+// What the search sends back as it goes through the files.
+enum GrepStatus {
+    File(GrepFile),
+    // The search stopped because there are more matches than the limit.
+    Limit,
+}
+
+pub enum GrepEvent {
+    Close,
+    Reveal(PathBuf, usize),
+    Open(PathBuf, usize),
+}
+
+/// A search of file contents under the root, with the results grouped by file.
+/// Files are searched on a background thread, and results are shown as they come in.
+#[derive(Default)]
+pub struct Grep {
+    active: bool,
+    root: PathBuf,
+    pattern: String,
+    max_matches: usize,
+
+    files: Vec<GrepFile>,
+    // The file and match index of every match, in the order they are shown.
+    matches: Vec<(usize, usize)>,
+    selected: usize,
+    // Set once the search stops, either because it finished or because it hit the limit.
+    done: bool,
+    truncated: bool,
+
+    receiver: Option<Receiver<GrepStatus>>,
+}
+
+impl Grep {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_matches: config.grep.max_matches,
+            ..Self::default()
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Starts searching every file the filter shows for the pattern, stopping after enough matches.
+    /// The pattern is a regex, which ignores case unless it has an uppercase letter.
+    pub fn open(&mut self, root: &Path, filter: &Filter, pattern: &str) -> anyhow::Result<()> {
+        let regex = Regex::builder()
+            .syntax(
+                syntax::Config::new()
+                    .case_insensitive(!pattern.chars().any(char::is_uppercase))
+                    .utf8(false),
+            )
+            .build(pattern)
+            .map_err(|_| anyhow!("{pattern} is not a valid pattern"))?;
+
+        let (sender, receiver) = channel();
+        let walker = filter.walker(root).build();
+        let walk_root = root.to_path_buf();
+        let max_matches = self.max_matches;
+        thread::spawn(move || {
+            let mut found = 0;
+            for entry in walker.flatten() {
+                if !entry.file_type().is_some_and(|x| x.is_file()) {
+                    continue;
+                }
+                // One match more than the limit is looked for, to tell whether the results are cut short.
+                let Some(mut file) = search_file(entry.path(), &regex, max_matches + 1 - found)
+                else {
+                    continue;
+                };
+                if let Ok(relative) = file.path.strip_prefix(&walk_root) {
+                    file.path = relative.to_path_buf();
+                }
+                found += file.matches.len();
+                if found > max_matches {
+                    file.matches.pop();
+                    if !file.matches.is_empty() {
+                        let _ = sender.send(GrepStatus::File(file));
+                    }
+                    let _ = sender.send(GrepStatus::Limit);
+                    break;
+                }
+                // The search has been closed or replaced once the receiver is dropped.
+                if sender.send(GrepStatus::File(file)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        *self = Self {
+            active: true,
+            root: root.to_path_buf(),
+            pattern: pattern.to_string(),
+            max_matches,
+            receiver: Some(receiver),
+            ..Self::default()
+        };
+        Ok(())
+    }
+
+    /// Shows the results of the last search again.
+    pub fn show(&mut self) -> bool {
+        self.active = self.receiver.is_some() || !self.files.is_empty();
+        self.active
+    }
+
+    /// Collects any files the search has gone through since the last update.
+    pub fn update(&mut self) {
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+        loop {
+            match receiver.try_recv() {
+                Ok(GrepStatus::File(file)) => {
+                    let idx = self.files.len();
+                    self.matches
+                        .extend((0..file.matches.len()).map(|x| (idx, x)));
+                    self.files.push(file);
+                }
+                Ok(GrepStatus::Limit) => self.truncated = true,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.done = true;
+                    self.receiver = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the number of matches, and the number of files they are in.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.matches.len(), self.files.len())
+    }
+
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    fn selected_match(&self) -> Option<(PathBuf, usize)> {
+        let (file, idx) = self.matches.get(self.selected)?;
+        let file = &self.files[*file];
+        Some((self.root.join(&file.path), file.matches[*idx].line))
+    }
+
+    pub fn handle(&mut self, k: KeyEvent) -> Option<GrepEvent> {
+        if !(k.kind == KeyEventKind::Press || k.kind == KeyEventKind::Repeat) {
+            return None;
+        }
+
+        let last = self.matches.len().saturating_sub(1);
+        match k.code {
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected = (self.selected + 1).min(last),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(10),
+            KeyCode::PageDown => self.selected = (self.selected + 10).min(last),
+            KeyCode::Enter => {
+                let (path, line) = self.selected_match()?;
+                self.active = false;
+                return Some(GrepEvent::Reveal(path, line));
+            }
+            KeyCode::Char('o') => {
+                let (path, line) = self.selected_match()?;
+                self.active = false;
+                return Some(GrepEvent::Open(path, line));
+            }
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.active = false;
+                return Some(GrepEvent::Close);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl Render for Grep {
+    fn render(&self, mut loc: Vec2, buffer: &mut Buffer) -> Vec2 {
+        let width = buffer.size().x.saturating_sub(loc.x);
+        let max_lines = (buffer.size().y as usize).saturating_sub(7);
+
+        // Each file's path is shown above its matches, so the rows are worked out before scrolling.
+        let mut rows = vec![];
+        let mut selected_row = 0;
+        for (i, (file, idx)) in self.matches.iter().enumerate() {
+            if *idx == 0 {
+                rows.push(Row::File(*file));
+            }
+            if i == self.selected {
+                selected_row = rows.len();
+            }
+            rows.push(Row::Match(i));
+        }
+        let start_line = (selected_row + 1).saturating_sub(max_lines);
+
+        render!(buffer, loc => [ format!("Grep: {}", self.pattern) ]);
+
+        for row in rows.iter().skip(start_line).take(max_lines) {
+            loc.y += 1;
+
+            let i = match row {
+                Row::Match(i) => *i,
+                Row::File(file) => {
+                    let path = self.files[*file].path.display().to_string();
+                    output::render_clipped(&[path.blue().bold()], loc, width, buffer);
+                    continue;
+                }
+            };
+            let (file, idx) = self.matches[i];
+            let grep_match = &self.files[file].matches[idx];
+            let cursor = match i == self.selected {
+                true => " > ",
+                false => "   ",
+            };
+            let spans = [
+                cursor.to_string().stylize(),
+                format!("{:>5}: ", grep_match.line).dark_grey(),
+                grep_match.before.clone().stylize(),
+                grep_match.matched.clone().black().on_yellow(),
+                grep_match.after.clone().stylize(),
+            ];
+            output::render_clipped(&spans, loc, width, buffer);
+        }
+        loc
+    }
+}
+
+/// Searches a file line by line for up to `limit` matches, returning nothing if it has none or looks binary.
+fn search_file(path: &Path, regex: &Regex, limit: usize) -> Option<GrepFile> {
+    // Files are read a line at a time, so large ones never have to fit in memory.
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    if reader.fill_buf().ok()?.contains(&0) {
+        return None;
+    }
+
+    let mut matches = vec![];
+    let mut line = vec![];
+    for i in 0.. {
+        if matches.len() >= limit {
+            break;
+        }
+        line.clear();
+        match reader.by_ref().take(MAX_LINE).read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let line = match line.strip_suffix(b"\n") {
+            Some(line) => line,
+            None => {
+                // The line was cut off, so the rest of it is skipped to get to the next one.
+                if reader.skip_until(b'\n').is_err() {
+                    break;
+                }
+                &line
+            }
+        };
+        let Some(found) = regex.find(line) else {
+            continue;
+        };
+        let before = clean(&line[..found.start()]);
+        let before = before.trim_start();
+        let skip = before.chars().count().saturating_sub(CONTEXT_BEFORE);
+        matches.push(GrepMatch {
+            line: i + 1,
+            before: before.chars().skip(skip).collect(),
+            matched: clean(&line[found.range()]),
+            after: clean(&line[found.end()..])
+                .chars()
+                .take(CONTEXT_AFTER)
+                .collect(),
+        });
+    }
+    if matches.is_empty() {
+        return None;
+    }
+    Some(GrepFile {
+        path: path.to_path_buf(),
+        matches,
+    })
+}
+
+/// Makes part of a line printable, expanding tabs and dropping control characters.
+fn clean(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .replace('\t', "    ")
+        .chars()
+        .filter(|x| !x.is_control())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use regex_automata::meta::Regex;
+
+    use super::{search_file, MAX_LINE};
+
+    #[test]
+    fn long_lines_are_cut_off() {
+        let path = std::env::temp_dir().join(format!("idex-grep-{}", std::process::id()));
+        let long = "a".repeat(MAX_LINE as usize * 3);
+        fs::write(&path, format!("{long}needle\nneedle\n")).unwrap();
+
+        let file = search_file(&path, &Regex::new("needle").unwrap(), 10);
+        fs::remove_file(&path).unwrap();
+        let lines = file
+            .unwrap()
+            .matches
+            .iter()
+            .map(|x| x.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [2]);
+    }
+}
@@ -47,6 +47,10 @@ impl Input {
         self.event.take()
     }
 
+    pub fn pending_event(&self) -> Option<&ExplorerEvent> {
+        self.event.as_ref()
+    }
+
     pub fn set_active(&mut self, active: bool) {
         self.active = active;
     }
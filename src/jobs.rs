@@ -0,0 +1,420 @@
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read},
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+};
+
+use ascii_forge::prelude::*;
+
+use crate::{
+    config::Config,
+    journal::Snapshot,
+    sh::{PreparedSh, Run},
+};
+
+// Variables that make most tools print colors even when their output isn't a terminal.
+// Left out when the user has asked for no colors with `NO_COLOR`.
+const FORCE_COLOR: [(&str, &str); 2] = [("CLICOLOR_FORCE", "1"), ("FORCE_COLOR", "1")];
+
+// How many finished jobs are kept around to look at, on top of every running one.
+const KEPT_JOBS: usize = 20;
+
+/// A line written by a job, and whether it was written to stderr.
+pub struct OutputLine {
+    pub text: String,
+    pub stderr: bool,
+}
+
+pub enum JobStatus {
+    Running,
+    Exited(ExitStatus),
+    Cancelled,
+    Failed(String),
+}
+
+impl JobStatus {
+    pub fn running(&self) -> bool {
+        matches!(self, Self::Running)
+    }
+}
+
+/// A shell command running in the background.
+/// A command run for each selected entry is a single job, whose runs happen one after another.
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    program: String,
+
+    // The arguments of every run that hasn't started yet.
+    runs: VecDeque<Run>,
+    child: Option<Child>,
+    // The first failed run's status, or the last run's if they all succeeded.
+    exit: Option<ExitStatus>,
+
+    sender: Sender<OutputLine>,
+    receiver: Receiver<OutputLine>,
+    // The newest lines of output, and how many older ones were dropped to keep it under `max_lines`.
+    pub lines: VecDeque<OutputLine>,
+    pub dropped: usize,
+    max_lines: usize,
+    pub status: JobStatus,
+    // Set when the job is cancelled, until `update` reports that it finished.
+    cancelled: bool,
+
+    // The directories as they were before the job started, so its changes can be journaled.
+    snapshot: Option<Snapshot>,
+}
+
+impl Job {
+    fn new(id: usize, sh: PreparedSh, max_lines: usize) -> Self {
+        let (sender, receiver) = channel();
+        let mut job = Self {
+            id,
+            command: sh.command,
+            program: sh.program,
+            runs: sh.runs.into(),
+            child: None,
+            exit: None,
+            sender,
+            receiver,
+            lines: VecDeque::new(),
+            dropped: 0,
+            max_lines,
+            status: JobStatus::Running,
+            cancelled: false,
+            snapshot: Some(sh.snapshot),
+        };
+        job.next_run();
+        job
+    }
+
+    /// Starts the next run, finishing the job if there are none left.
+    fn next_run(&mut self) {
+        let Some(run) = self.runs.pop_front() else {
+            self.status = self.exit.map_or(
+                JobStatus::Failed("nothing to run".to_string()),
+                JobStatus::Exited,
+            );
+            return;
+        };
+
+        // The job gets its own process group, so cancelling it also stops anything it started.
+        // Its output goes through pipes, so tools that support it are asked to keep their colors.
+        let spawned = Command::new(&self.program)
+            .args(run.args)
+            .envs(
+                FORCE_COLOR
+                    .into_iter()
+                    .filter(|_| std::env::var_os("NO_COLOR").is_none()),
+            )
+            .envs(run.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                if let Some(stdout) = child.stdout.take() {
+                    read_lines(stdout, false, self.sender.clone());
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    read_lines(stderr, true, self.sender.clone());
+                }
+                self.child = Some(child);
+            }
+            Err(e) => {
+                self.runs.clear();
+                self.status = JobStatus::Failed(format!("failed to run {}: {e}", self.program));
+            }
+        }
+    }
+
+    /// Collects output, and moves on to the next run once the current one exits.
+    /// Returns true if the job just finished.
+    fn update(&mut self) -> bool {
+        self.lines.extend(self.receiver.try_iter());
+        let over = self.lines.len().saturating_sub(self.max_lines.max(1));
+        self.lines.drain(..over);
+        self.dropped += over;
+        if !self.status.running() {
+            return std::mem::take(&mut self.cancelled);
+        }
+
+        let Some(child) = &mut self.child else {
+            return false;
+        };
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                self.child = None;
+                if self.exit.is_none_or(|x| x.success()) {
+                    self.exit = Some(status);
+                }
+                self.next_run();
+                !self.status.running()
+            }
+            Ok(None) => false,
+            Err(e) => {
+                self.child = None;
+                self.runs.clear();
+                self.status = JobStatus::Failed(e.to_string());
+                true
+            }
+        }
+    }
+
+    /// Kills the job's process group, and skips any runs that are left.
+    /// The next `update` reports the job as finished, so what it did before it was killed is journaled.
+    fn cancel(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            // SAFETY: `kill` has no memory safety requirements, the group id is the child's pid.
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            let _ = child.wait();
+        }
+        self.runs.clear();
+        self.status = JobStatus::Cancelled;
+        self.cancelled = true;
+    }
+
+    /// Takes the snapshot taken when the job started, once it has finished.
+    pub fn take_snapshot(&mut self) -> Option<Snapshot> {
+        self.snapshot.take()
+    }
+
+    /// Describes the job's status in a few words.
+    pub fn describe_status(&self) -> String {
+        match &self.status {
+            JobStatus::Running => "running".to_string(),
+            JobStatus::Exited(status) => match status.code() {
+                Some(code) => format!("exit {code}"),
+                None => "killed".to_string(),
+            },
+            JobStatus::Cancelled => "cancelled".to_string(),
+            JobStatus::Failed(e) => format!("error: {e}"),
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        matches!(self.status, JobStatus::Exited(status) if status.success())
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // Nothing would be left to read the output, so running jobs are stopped along with idex.
+        if self.status.running() {
+            self.cancel();
+        }
+    }
+}
+
+/// Sends every line read from the pipe, until it is closed.
+fn read_lines(pipe: impl Read + Send + 'static, stderr: bool, sender: Sender<OutputLine>) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buf = vec![];
+        while let Ok(1..) = reader.read_until(b'\n', &mut buf) {
+            let text = String::from_utf8_lossy(&buf);
+            let text = text.trim_end_matches(['\n', '\r']);
+            // Progress bars redraw the line with carriage returns, only the last one is kept.
+            let text = text.rsplit('\r').next().unwrap_or_default().to_string();
+            if sender.send(OutputLine { text, stderr }).is_err() {
+                break;
+            }
+            buf.clear();
+        }
+    });
+}
+
+pub enum JobsEvent {
+    Close,
+    Show,
+}
+
+/// Every background job, which one's output is shown in the log, and the list used to pick it.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    next_id: usize,
+    max_lines: usize,
+
+    shown: Option<usize>,
+    // How many lines up from the end of the output the log is scrolled,
+    // and how many lines of it fit on screen, as of the last render.
+    scroll: usize,
+    height: Cell<usize>,
+
+    list_active: bool,
+    selected: usize,
+}
+
+impl Jobs {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_lines: config.jobs.max_lines,
+            ..Default::default()
+        }
+    }
+
+    /// Starts a job and shows its output.
+    pub fn spawn(&mut self, sh: PreparedSh) {
+        self.next_id += 1;
+        self.jobs.push(Job::new(self.next_id, sh, self.max_lines));
+        self.shown = Some(self.next_id);
+        self.scroll = 0;
+
+        // Forget the oldest finished jobs.
+        while self.jobs.iter().filter(|x| !x.status.running()).count() > KEPT_JOBS {
+            let idx = self
+                .jobs
+                .iter()
+                .position(|x| !x.status.running())
+                .expect("There should be a finished job");
+            self.jobs.remove(idx);
+        }
+    }
+
+    /// Updates every job, returning the ones that just finished.
+    pub fn update(&mut self) -> Vec<&mut Job> {
+        self.jobs
+            .iter_mut()
+            .filter_map(|x| x.update().then_some(x))
+            .collect()
+    }
+
+    pub fn shown(&self) -> Option<&Job> {
+        self.jobs.iter().find(|x| Some(x.id) == self.shown)
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    pub fn set_height(&self, height: usize) {
+        self.height.set(height);
+    }
+
+    /// Scrolls the shown output, positive distances moving towards the end.
+    pub fn scroll_by(&mut self, dist: i32) {
+        let len = self.shown().map_or(0, |x| x.lines.len());
+        let max = len.saturating_sub(self.height.get().max(1));
+        self.scroll = match dist < 0 {
+            true => (self.scroll + dist.unsigned_abs() as usize).min(max),
+            false => self.scroll.saturating_sub(dist as usize),
+        };
+    }
+
+    /// Cancels the shown job, or the newest running job if it has already finished.
+    /// Returns the cancelled job's command.
+    pub fn cancel(&mut self) -> Option<String> {
+        let shown = self.shown;
+        let job = match self
+            .jobs
+            .iter()
+            .position(|x| Some(x.id) == shown && x.status.running())
+        {
+            Some(idx) => &mut self.jobs[idx],
+            None => self.jobs.iter_mut().rev().find(|x| x.status.running())?,
+        };
+        job.cancel();
+        Some(job.command.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn running(&self) -> usize {
+        self.jobs.iter().filter(|x| x.status.running()).count()
+    }
+
+    pub fn list_active(&self) -> bool {
+        self.list_active
+    }
+
+    /// Opens the list of jobs, newest first, with the shown job selected.
+    pub fn open_list(&mut self) {
+        self.selected = self
+            .jobs
+            .iter()
+            .rev()
+            .position(|x| Some(x.id) == self.shown)
+            .unwrap_or(0);
+        self.list_active = true;
+    }
+
+    pub fn handle(&mut self, k: KeyEvent) -> Option<JobsEvent> {
+        if !(k.kind == KeyEventKind::Press || k.kind == KeyEventKind::Repeat) {
+            return None;
+        }
+
+        let len = self.jobs.len();
+        match k.code {
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(len.saturating_sub(1))
+            }
+            KeyCode::Char('c') => {
+                if let Some(job) = self.jobs.iter_mut().rev().nth(self.selected) {
+                    if job.status.running() {
+                        job.cancel();
+                    }
+                }
+            }
+            KeyCode::Enter => {
+                let job = self.jobs.iter().rev().nth(self.selected)?;
+                self.shown = Some(job.id);
+                self.scroll = 0;
+                self.list_active = false;
+                return Some(JobsEvent::Show);
+            }
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.list_active = false;
+                return Some(JobsEvent::Close);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl Render for Jobs {
+    fn render(&self, mut loc: Vec2, buffer: &mut Buffer) -> Vec2 {
+        let max_lines = (buffer.size().y as usize)
+            .saturating_sub(7)
+            .min(self.jobs.len());
+        let start_line = (self.selected + 1).saturating_sub(max_lines);
+
+        render!(buffer, loc => [ "Jobs" ]);
+
+        for (i, job) in self
+            .jobs
+            .iter()
+            .rev()
+            .enumerate()
+            .skip(start_line)
+            .take(max_lines)
+        {
+            loc.y += 1;
+
+            let status = format!("[{}] {} ", job.id, job.describe_status());
+            let status = match (job.status.running(), job.succeeded()) {
+                (true, _) => status.yellow(),
+                (false, true) => status.green(),
+                (false, false) => status.red(),
+            };
+            if i == self.selected {
+                render!(buffer, loc => [ " > ", status, job.command.as_str().green(), " <" ]);
+            } else {
+                render!(buffer, loc => [ "   ", status, job.command.as_str() ]);
+            }
+        }
+        loc
+    }
+}
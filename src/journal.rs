@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::file_ops;
+
+// Directories with more entries than this are left out of snapshots, so reading them doesn't hold up
+// running a command, and what commands do inside of them isn't journaled.
+const MAX_SNAPSHOT_ENTRIES: usize = 2000;
+
+/// A file operation done by idex, stored as the moves needed to redo it.
+/// Creating a file is a move out of the trash, and deleting one is a move into it,
+/// so every operation can be undone by running its moves backwards.
+#[derive(Clone)]
+pub struct Operation {
+    pub description: String,
+    moves: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Operation {
+    /// Returns every path the operation touches.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.moves
+            .iter()
+            .flat_map(|(from, to)| [from.as_path(), to.as_path()])
+    }
+}
+
+/// The session's operation journal, along with the trash directories that deleted entries are moved to.
+pub struct Journal {
+    // The session's directory in a trash on each device entries were deleted from, so moving
+    // them there is a rename rather than a copy.
+    trashes: HashMap<u64, PathBuf>,
+    // Unique to the session, since process ids get reused.
+    session: String,
+    next_slot: usize,
+
+    done: Vec<Operation>,
+    undone: Vec<Operation>,
+    // Every path moved by recording, undoing or redoing an operation, oldest first.
+    touched: Vec<PathBuf>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos())
+            .unwrap_or_default();
+        let session = format!("{}-{started}", std::process::id());
+        purge_stale(home_trash(), session.clone());
+        Self {
+            trashes: HashMap::new(),
+            session,
+            next_slot: 0,
+            done: vec![],
+            undone: vec![],
+            touched: vec![],
+        }
+    }
+
+    /// Returns the session's trash on the same device as the path. Devices that one can't be
+    /// made on share the trash in the cache directory.
+    fn trash_dir(&mut self, path: &Path) -> anyhow::Result<PathBuf> {
+        let dir = fs::canonicalize(path.parent().unwrap_or(path))?;
+        let dev = dir.metadata()?.dev();
+        if let Some(trash) = self.trashes.get(&dev) {
+            return Ok(trash.clone());
+        }
+
+        let home = home_trash();
+        fs::create_dir_all(&home)?;
+        let mut base = home.clone();
+        if home.metadata()?.dev() != dev {
+            // SAFETY: `getuid` has no memory safety requirements.
+            let uid = unsafe { libc::getuid() };
+            let device = mount_point(&dir, dev).join(format!(".idex-trash-{uid}"));
+            if fs::create_dir_all(&device).is_ok() {
+                purge_stale(device.clone(), self.session.clone());
+                base = device;
+            }
+        }
+
+        let trash = base.join(&self.session);
+        fs::create_dir_all(&trash)?;
+        self.trashes.insert(dev, trash.clone());
+        Ok(trash)
+    }
+
+    /// Returns a new, unused path inside of the trash on the path's device.
+    pub fn slot(&mut self, path: &Path) -> anyhow::Result<PathBuf> {
+        let dir = self.trash_dir(path)?.join(self.next_slot.to_string());
+        self.next_slot += 1;
+        fs::create_dir_all(&dir)?;
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        Ok(dir.join(name))
+    }
+
+    /// Moves the path into the trash, returning the move so it can be recorded.
+    pub fn trash(&mut self, path: &Path) -> anyhow::Result<(PathBuf, PathBuf)> {
+        let slot = self.slot(path)?;
+        file_ops::move_path(path, &slot)?;
+        Ok((path.to_path_buf(), slot))
+    }
+
+    /// Returns the move that would have created `path` out of the trash.
+    pub fn created(&mut self, path: &Path) -> anyhow::Result<(PathBuf, PathBuf)> {
+        Ok((self.slot(path)?, path.to_path_buf()))
+    }
+
+    /// Records an operation that has already been done, clearing anything that could be redone.
+    pub fn record(&mut self, description: String, moves: Vec<(PathBuf, PathBuf)>) {
+        if moves.is_empty() {
+            return;
+        }
+        self.undone.clear();
+        let op = Operation { description, moves };
+        self.touched.extend(op.paths().map(Path::to_path_buf));
+        self.done.push(op);
+    }
+
+    /// Marks how far the journal has got, to tell later which paths were touched since.
+    pub fn mark(&self) -> usize {
+        self.touched.len()
+    }
+
+    pub fn undo(&mut self) -> anyhow::Result<Operation> {
+        let op = self.done.pop().ok_or(anyhow!("nothing to undo"))?;
+        let moves = op.moves.iter().rev().map(|(from, to)| (to, from));
+        if let Err(e) = run_moves(moves) {
+            self.done.push(op);
+            return Err(e);
+        }
+        self.touched.extend(op.paths().map(Path::to_path_buf));
+        self.undone.push(op.clone());
+        Ok(op)
+    }
+
+    pub fn redo(&mut self) -> anyhow::Result<Operation> {
+        let op = self.undone.pop().ok_or(anyhow!("nothing to redo"))?;
+        let moves = op.moves.iter().map(|(from, to)| (from, to));
+        if let Err(e) = run_moves(moves) {
+            self.undone.push(op);
+            return Err(e);
+        }
+        self.touched.extend(op.paths().map(Path::to_path_buf));
+        self.done.push(op.clone());
+        Ok(op)
+    }
+
+    /// Describes the newest operations on a single line, with undone operations shown before the rest.
+    pub fn summary(&self, count: usize) -> String {
+        if self.done.is_empty() && self.undone.is_empty() {
+            return "Journal is empty".to_string();
+        }
+        let operations = self
+            .undone
+            .iter()
+            .map(|x| format!("(undone) {}", x.description))
+            .chain(self.done.iter().rev().map(|x| x.description.clone()))
+            .take(count)
+            .collect::<Vec<_>>();
+        format!("Journal: {}", operations.join("; "))
+    }
+}
+
+/// Runs the moves in order. If one fails, the ones already done are moved back, so that
+/// the operation can be tried again as a whole.
+fn run_moves<'a>(moves: impl Iterator<Item = (&'a PathBuf, &'a PathBuf)>) -> anyhow::Result<()> {
+    let mut done = vec![];
+    for (from, to) in moves {
+        let Err(e) = file_ops::move_path(from, to) else {
+            done.push((from, to));
+            continue;
+        };
+        let stuck: Vec<_> = done
+            .into_iter()
+            .rev()
+            .filter(|(from, to)| file_ops::move_path(to, from).is_err())
+            .map(|(_, to)| to.display().to_string())
+            .collect();
+        if !stuck.is_empty() {
+            bail!("{e}, and {} couldn't be moved back", stuck.join(", "));
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        // The journal only lasts for the session, so anything left in the trash is deleted for good.
+        for trash in self.trashes.values() {
+            let _ = fs::remove_dir_all(trash);
+        }
+    }
+}
+
+/// The trash in the cache directory, holding each session's deleted entries in a directory of its own.
+fn home_trash() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or(std::env::temp_dir())
+        .join("idex/trash")
+}
+
+/// Returns the topmost directory above the path that is still on the same device.
+fn mount_point(path: &Path, dev: u64) -> &Path {
+    path.ancestors()
+        .take_while(|x| x.metadata().is_ok_and(|x| x.dev() == dev))
+        .last()
+        .unwrap_or(path)
+}
+
+/// Deletes what sessions that aren't running anymore left in a trash, like after a crash,
+/// in the background since that can be a lot.
+fn purge_stale(base: PathBuf, session: String) {
+    thread::spawn(move || {
+        let Ok(read_dir) = fs::read_dir(&base) else {
+            return;
+        };
+        for entry in read_dir.flatten() {
+            let name = entry.file_name();
+            let Some(name) = name.to_str().filter(|x| *x != session) else {
+                continue;
+            };
+            let Some(pid) = name.split('-').next().and_then(|x| x.parse().ok()) else {
+                continue;
+            };
+            // A directory under this process' id was left by an earlier one that had the same id.
+            if pid == std::process::id() || !running(pid) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    });
+}
+
+/// Checks whether a process with the id exists, without signalling it.
+fn running(pid: u32) -> bool {
+    // SAFETY: `kill` has no memory safety requirements, and signal 0 only checks the process exists.
+    let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The contents of some directories, used to detect what a shell command did.
+pub struct Snapshot {
+    dirs: HashMap<PathBuf, HashMap<OsString, Identity>>,
+    // The journal's mark when the snapshot was taken.
+    since: usize,
+}
+
+/// What an entry is known by, to tell whether one that appeared is one that disappeared.
+#[derive(Clone, Copy, PartialEq)]
+struct Identity {
+    dev: u64,
+    ino: u64,
+    size: u64,
+}
+
+impl Snapshot {
+    pub fn take(dirs: impl IntoIterator<Item = PathBuf>, journal: &Journal) -> Self {
+        Self {
+            dirs: read_dirs(dirs),
+            since: journal.mark(),
+        }
+    }
+
+    /// Compares the snapshot to the directories as they are now, returning the moves that can be undone.
+    /// An entry that disappeared is paired with one that appeared if it is the same file, or if it has
+    /// the same name and size, like after a move across file systems. That is a rename or a move,
+    /// and anything else that appeared was created. Deleted entries are gone for good, so they aren't returned.
+    /// Paths the journal recorded since the snapshot was taken, like while a job ran in the background,
+    /// are left out so they aren't journaled twice, and so are directories too large to be snapshotted.
+    pub fn changes(&self, journal: &mut Journal) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+        let after = read_dirs(self.dirs.keys().cloned());
+        let touched = &journal.touched[self.since..];
+        let untouched = |path: &PathBuf| !touched.iter().any(|x| path.starts_with(x));
+
+        let mut removed = vec![];
+        let mut added = vec![];
+        for (dir, before) in &self.dirs {
+            let Some(now) = after.get(dir) else {
+                continue;
+            };
+            removed.extend(
+                before
+                    .iter()
+                    .filter(|x| !now.contains_key(x.0))
+                    .map(|(name, id)| (dir.join(name), *id))
+                    .filter(|x| untouched(&x.0)),
+            );
+            added.extend(
+                now.iter()
+                    .filter(|x| !before.contains_key(x.0))
+                    .map(|(name, id)| (dir.join(name), *id))
+                    .filter(|x| untouched(&x.0)),
+            );
+        }
+
+        let mut moves = vec![];
+        for (from, id) in removed {
+            let same_file = added
+                .iter()
+                .position(|x| (x.1.dev, x.1.ino) == (id.dev, id.ino));
+            let same_name = || {
+                added
+                    .iter()
+                    .position(|x| x.0.file_name() == from.file_name() && x.1.size == id.size)
+            };
+            if let Some(idx) = same_file.or_else(same_name) {
+                moves.push((from, added.remove(idx).0));
+            }
+        }
+        for (path, _) in added {
+            moves.push(journal.created(&path)?);
+        }
+        Ok(moves)
+    }
+}
+
+/// Reads what is in each of the directories, leaving the ones that can't be read empty,
+/// and leaving out the ones with more than `MAX_SNAPSHOT_ENTRIES` entries.
+fn read_dirs(
+    dirs: impl IntoIterator<Item = PathBuf>,
+) -> HashMap<PathBuf, HashMap<OsString, Identity>> {
+    dirs.into_iter()
+        .filter_map(|dir| {
+            let listed = match dir.read_dir() {
+                Ok(read_dir) => read_dir
+                    .flatten()
+                    .take(MAX_SNAPSHOT_ENTRIES + 1)
+                    .collect::<Vec<_>>(),
+                Err(_) => vec![],
+            };
+            if listed.len() > MAX_SNAPSHOT_ENTRIES {
+                return None;
+            }
+            let entries = listed
+                .into_iter()
+                .filter_map(|x| {
+                    let meta = x.path().symlink_metadata().ok()?;
+                    let identity = Identity {
+                        dev: meta.dev(),
+                        ino: meta.ino(),
+                        size: meta.len(),
+                    };
+                    Some((x.file_name(), identity))
+                })
+                .collect();
+            Some((dir, entries))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{run_moves, Journal, Snapshot, MAX_SNAPSHOT_ENTRIES};
+
+    #[test]
+    fn failed_moves_are_rolled_back() {
+        let dir = std::env::temp_dir().join(format!("idex-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|x| dir.join(x));
+        fs::write(&a, "a").unwrap();
+
+        // `c` doesn't exist, so the second move fails and `b` goes back to `a`.
+        let moves = [(a.clone(), b.clone()), (c, d)];
+        let res = run_moves(moves.iter().map(|(from, to)| (from, to)));
+        let restored = a.exists() && !b.exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(res.is_err());
+        assert!(restored);
+    }
+
+    #[test]
+    fn large_directories_are_left_out_of_snapshots() {
+        let dir = std::env::temp_dir().join(format!("idex-snapshot-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let [small, large] = ["small", "large"].map(|x| dir.join(x));
+        fs::create_dir_all(&small).unwrap();
+        fs::create_dir_all(&large).unwrap();
+        for i in 0..=MAX_SNAPSHOT_ENTRIES {
+            fs::write(large.join(i.to_string()), "").unwrap();
+        }
+
+        let mut journal = Journal::new();
+        let snapshot = Snapshot::take([small.clone(), large.clone()], &journal);
+        fs::write(small.join("new"), "").unwrap();
+        fs::write(large.join("new"), "").unwrap();
+        let changes = snapshot.changes(&mut journal);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let created = changes
+            .unwrap()
+            .into_iter()
+            .map(|x| x.1)
+            .collect::<Vec<_>>();
+        assert_eq!(created, [small.join("new")]);
+    }
+}
@@ -1,37 +1,119 @@
 use std::{
-    env::{args, current_dir},
+    env::{args_os, current_dir},
+    io::Write,
     time::Duration,
 };
 
+use app::App;
 use ascii_forge::prelude::*;
+use cli::{Action, Cli};
 use click_data::ClickData;
-use config::Config;
-use confirmation::Confirmation;
+use config::{Config, DEFAULT_CONFIG};
 use crokey::Combiner;
 use events::ExplorerEvent;
 use explorer::Explorer;
-use input::{Input, InputEvent};
-use sh::handle_sh;
+use finder::FinderEvent;
+use input::InputEvent;
+use jobs::JobsEvent;
+use paste::ConflictEvent;
+use trash::TrashEvent;
+
+mod app;
 
 mod dir_items;
 mod entry;
+mod file_ops;
+mod filter;
+mod git;
+mod journal;
+mod paste;
+mod preview;
+mod trash;
+mod tree;
+mod viewer;
 
 mod events;
 mod explorer;
+mod watcher;
 
 mod config;
+mod display;
 mod style;
 
+mod cli;
 mod click_data;
+mod columns;
 
+mod session;
 mod sh;
+mod sort;
 
 mod confirmation;
+mod finder;
+mod grep;
 mod input;
+mod jobs;
+mod output;
+
+// Modes, owners, the trash, jobs and chooser mode all rely on unix APIs.
+#[cfg(not(unix))]
+compile_error!("idex only supports unix-like systems, like Linux and macOS");
 
 fn main() -> anyhow::Result<()> {
-    let mut input = Input::new();
-    let mut confirmation = Confirmation::new();
+    // Everything that can go wrong with the arguments or the config is reported before the window takes over the terminal.
+    let cli = Cli::parse(args_os().skip(1))?;
+
+    match cli.action {
+        Some(Action::PrintDefaultConfig) => {
+            print!("{DEFAULT_CONFIG}");
+            return Ok(());
+        }
+        Some(Action::Help) => {
+            println!("{}", cli::HELP);
+            return Ok(());
+        }
+        Some(Action::Version) => {
+            println!("idex {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Some(Action::CheckConfig) | None => {}
+    }
+
+    // Load configuration from file system.
+    let config = match cli.no_config {
+        true => Config::default_config()?,
+        false => Config::load(cli.config.as_deref())?,
+    };
+
+    if let Some(Action::CheckConfig) = cli.action {
+        let path = cli.config.unwrap_or(Config::default_path());
+        match cli.no_config || !path.exists() {
+            true => println!("Using the default config"),
+            false => println!("{} is valid", path.display()),
+        }
+        return Ok(());
+    }
+
+    // Set path to local if no path is passed into the arguments
+    let path = match cli.path {
+        Some(path) => path,
+        None => current_dir()?,
+    };
+
+    let mut app = App::new(&config);
+
+    // Create explorer and load the default folder automatically
+    let mut explorer = Explorer::new(&path, &config, !cli.fresh)?;
+    if let Some(reveal) = &cli.reveal {
+        explorer.reveal(reveal)?;
+    }
+
+    // Picked paths are written to the original stdout once the window is gone.
+    let mut pick_output = match cli.pick {
+        true => Some(cli::redirect_stdout()?),
+        false => None,
+    };
+    let mut picked = vec![];
 
     let mut last_click = ClickData::default();
 
@@ -39,30 +121,20 @@ fn main() -> anyhow::Result<()> {
     let mut combiner = Combiner::default();
     combiner.enable_combining()?;
 
-    // Set path to local if no path is passed into the arguments
-    let path = args()
-        .collect::<Vec<String>>()
-        .get(1)
-        .cloned()
-        .unwrap_or(current_dir()?.into_os_string().into_string().unwrap());
-
     // Initialize the window and have the window handle panics automatically
     let mut window = Window::init()?;
     handle_panics();
 
-    // Load configuration from file system.
-    let config = Config::load()?;
-
-    // Create explorer and load the default folder automatically
-    let mut explorer = Explorer::new(&path, &config)?;
-
-    // A string for a previous log.
-    let mut log_string = String::new();
-
     // The main exploring loop
-    loop {
-        // Re-read the file system for new changes.
-        explorer.refresh()?;
+    'main: loop {
+        // Apply any changes the file system watcher has seen.
+        if let Err(e) = explorer.update() {
+            app.log_string = format!("Error: {e}");
+        }
+
+        // Poll background tasks.
+        app.update(&mut explorer)?;
+        app.preview.update(&explorer, &config);
 
         // If control-c is pressed, quit the program. (reserved command)
         if event!(window, Event::Key(k) => *k == KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL))
@@ -72,6 +144,7 @@ fn main() -> anyhow::Result<()> {
 
         // Loop through collected events.
         let events = window.events();
+        let mut resized = false;
         for event in events {
             match event {
                 Event::Mouse(m) => {
@@ -79,8 +152,11 @@ fn main() -> anyhow::Result<()> {
                         let idx = m.row as usize;
                         let click_data = ClickData::new(idx);
 
-                        // Ignore click if clicking on ./ path.
-                        if idx == 0 {
+                        let tree_width =
+                            window.size().x - app.preview.width(&config, window.size().x);
+
+                        // Ignore click if clicking on ./ path, or on the preview.
+                        if idx == 0 || m.column >= tree_width {
                             continue;
                         }
 
@@ -91,31 +167,25 @@ fn main() -> anyhow::Result<()> {
                         if last_click.is_double(&click_data, &config)
                             && explorer.selection_valid(idx - 1)
                         {
-                            if explorer.is_file() {
+                            if explorer.is_file() && cli.pick {
+                                // In chooser mode, double clicking a file picks it.
+                                picked = pick(&explorer);
+                                if !picked.is_empty() {
+                                    break 'main;
+                                }
+                            } else if explorer.is_file() {
                                 // If file double clicked, run configured command.
 
                                 if let Some(event) = config.double_click.clone() {
-                                    match event {
-                                        ExplorerEvent::Quit => return Ok(()),
-                                        ExplorerEvent::Sh { command, args } => handle_sh(
-                                            &explorer,
-                                            command,
-                                            args,
-                                            &mut log_string,
-                                            None,
-                                        ),
-                                        ExplorerEvent::Input { event } => {
-                                            input.set_event(*event);
-                                        }
-                                        ExplorerEvent::Confirmation { event } => {
-                                            confirmation.set(*event);
-                                        }
-                                        _ => explorer.handle_event(event)?,
+                                    if app.run_event(event, &mut explorer, None)? {
+                                        break 'main;
                                     }
                                 }
                             } else {
                                 // If double clicked folder, expand/collapse it.
-                                explorer.toggle()?;
+                                if let Err(e) = explorer.toggle() {
+                                    app.log_string = format!("Error: {e}");
+                                }
                             }
                             // Reset click to impossible line.
                             last_click = ClickData::default()
@@ -134,123 +204,346 @@ fn main() -> anyhow::Result<()> {
                     }
                 }
                 Event::Key(k) => {
-                    if let Some(e) = input.event(*k) {
-                        match e {
-                            InputEvent::Cancel => {
-                                input.set_active(false);
-                                input.clear();
+                    if app.finder.active() {
+                        if let Some(FinderEvent::Accept(path)) = app.finder.handle(*k) {
+                            if let Err(e) = explorer.reveal(&path) {
+                                app.log_string = format!("Error: {e}");
                             }
-                            InputEvent::Accept => {
-                                let text = input.text();
-                                if let Some(event) = input.take_event() {
-                                    match event {
-                                        ExplorerEvent::Quit => return Ok(()),
-                                        ExplorerEvent::Sh { command, args } => handle_sh(
-                                            &explorer,
-                                            command,
-                                            args,
-                                            &mut log_string,
-                                            Some(text),
-                                        ),
-                                        ExplorerEvent::Input { event } => {
-                                            input.set_event(*event);
-                                            input.set_active(true);
-                                        }
-                                        ExplorerEvent::Confirmation { event } => {
-                                            confirmation.set(*event);
-                                        }
-
-                                        _ => explorer.handle_event(event.clone())?,
-                                    }
-                                }
-                                input.set_active(false);
+                        }
+                        continue;
+                    }
+
+                    if app.output_expanded && app.handle_output_key(*k) {
+                        continue;
+                    }
+
+                    if app.jobs.list_active() {
+                        if let Some(JobsEvent::Show) = app.jobs.handle(*k) {
+                            app.log_string.clear();
+                        }
+                        continue;
+                    }
+
+                    if app.grep.active() {
+                        if let Some(event) = app.grep.handle(*k) {
+                            if app.grep_event(event, &mut explorer, &config)? {
+                                break 'main;
                             }
                         }
+                        continue;
                     }
-                    if input.active() {
+
+                    if app.viewer.active() {
+                        app.viewer.handle(*k);
                         continue;
                     }
 
-                    if confirmation.active() {
-                        let event = confirmation.take().expect("Confirmation should be Some");
-                        if confirmation.handle(*k) {
-                            match event {
-                                ExplorerEvent::Quit => return Ok(()),
-                                ExplorerEvent::Sh { command, args } => {
-                                    handle_sh(&explorer, command, args, &mut log_string, None)
-                                }
-                                ExplorerEvent::Input { event } => {
-                                    input.set_event(*event);
-                                    input.set_active(true);
-                                }
-                                ExplorerEvent::Confirmation { event } => {
-                                    confirmation.set(*event);
+                    if app.trash.active() {
+                        if let Some(TrashEvent::Restore(item)) = app.trash.handle(*k) {
+                            app.restore(&item, &mut explorer);
+                        }
+                        continue;
+                    }
+
+                    if let Some(paste) = &mut app.paste {
+                        match paste.handle(*k) {
+                            Some(ConflictEvent::Cancel) => app.paste = None,
+                            Some(ConflictEvent::Resolved) => {
+                                let paste = app.paste.take().expect("Paste should be Some");
+                                app.start_paste(paste, &mut explorer);
+                            }
+                            None => {}
+                        }
+                        continue;
+                    }
+
+                    if app.input.active() {
+                        match app.input.event(*k) {
+                            Some(InputEvent::Cancel) => app.cancel_input(&mut explorer),
+                            Some(InputEvent::Accept) => {
+                                let text = app.input.text();
+                                app.input.set_active(false);
+                                if let Some(event) = app.input.take_event() {
+                                    if app.run_event(event, &mut explorer, Some(text))? {
+                                        break 'main;
+                                    }
                                 }
+                            }
+                            None => app.input_changed(&mut explorer),
+                        }
+                        continue;
+                    }
 
-                                _ => explorer.handle_event(event.clone())?,
+                    if app.confirmation.active() {
+                        let event = app
+                            .confirmation
+                            .take()
+                            .expect("Confirmation should be Some");
+                        if app.confirmation.handle(*k)
+                            && app.run_event(event, &mut explorer, None)?
+                        {
+                            break 'main;
+                        }
+                        continue;
+                    }
+                    // In chooser mode, enter picks the selected files instead of running its binding,
+                    // and expands or collapses a directory like double clicking does.
+                    if cli.pick && k.code == KeyCode::Enter && k.kind == KeyEventKind::Press {
+                        if !explorer.is_file() {
+                            if let Err(e) = explorer.toggle() {
+                                app.log_string = format!("Error: {e}");
                             }
+                            continue;
+                        }
+                        picked = pick(&explorer);
+                        if !picked.is_empty() {
+                            break 'main;
                         }
                         continue;
                     }
+
                     // Find the keybind pressed, and run the binding that is pressed, if a configuration is written.
                     if let Some(key_combo) = combiner.transform(*k) {
                         if let Some(event) = config.bindings.get(&key_combo) {
-                            match event.clone() {
-                                ExplorerEvent::Quit => return Ok(()),
-                                ExplorerEvent::Sh { command, args } => {
-                                    handle_sh(&explorer, command, args, &mut log_string, None)
-                                }
-                                ExplorerEvent::Input { event } => {
-                                    input.set_event(*event);
-                                    input.set_active(true);
-                                }
-                                ExplorerEvent::Confirmation { event } => {
-                                    confirmation.set(*event);
-                                }
-
-                                _ => explorer.handle_event(event.clone())?,
+                            if app.run_event(event.clone(), &mut explorer, None)? {
+                                break 'main;
                             }
                         }
                     }
                 }
+                Event::Resize(..) => resized = true,
                 _ => {}
             }
         }
+        // The window starts over with blank buffers, so clear whatever the terminal kept.
+        if resized {
+            crossterm::execute!(
+                window.io(),
+                crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
+            )?;
+        }
+        // Hand the terminal over to an interactive command, and take it back once it exits.
+        if let Some(sh) = app.suspended.take() {
+            combiner.disable_combining()?;
+            drop(window);
+
+            app.log_string = match sh.run_interactive(&mut explorer) {
+                Ok(status) => status,
+                Err(e) => format!("Error: {e}"),
+            };
+
+            window = Window::init()?;
+            combiner.enable_combining()?;
+            if let Err(e) = explorer.refresh() {
+                app.log_string = format!("Error: {e}");
+            }
+        }
+
         // Render window, border, and log-string to the screen.
-        render!(window,
-            vec2(0, 0) => [ explorer ],
-        );
-        if confirmation.active() {
+        if app.finder.active() {
+            render!(window,
+                vec2(0, 0) => [ app.finder ],
+            );
+        } else if app.grep.active() {
+            render!(window,
+                vec2(0, 0) => [ app.grep ],
+            );
+        } else if app.viewer.active() {
+            render!(window,
+                vec2(0, 0) => [ app.viewer ],
+            );
+        } else if app.trash.active() {
+            render!(window,
+                vec2(0, 0) => [ app.trash ],
+            );
+        } else if app.jobs.list_active() {
+            render!(window,
+                vec2(0, 0) => [ app.jobs ],
+            );
+        } else if app.preview.enabled {
+            // Split the window, with the tree on the left and the preview on the right.
+            let size = window.size();
+            let preview_width = app.preview.width(&config, size.x);
+            let mut tree = Buffer::new(vec2(size.x - preview_width, size.y));
+            render!(tree, vec2(0, 0) => [ explorer ]);
+            render!(window, vec2(0, 0) => [ tree ]);
+
+            let loc = vec2(size.x - preview_width, 0);
+            let preview_size = vec2(preview_width, size.y.saturating_sub(6));
+            app.preview
+                .render_at(loc, preview_size, window.buffer_mut(), &config);
+        } else {
+            render!(window,
+                vec2(0, 0) => [ explorer ],
+            );
+        }
+        if app.finder.active() {
+            let (matches, total) = app.finder.counts();
+            let title = format!("FIND {matches}/{total} ");
+            render!( window,
+                vec2(0, window.size().y - 6) =>
+                [
+                    title.as_str().green(), "─".repeat((window.size().x as usize).saturating_sub(title.chars().count())).green()
+                ],
+                vec2(0, window.size().y - 5) =>
+                [
+                    ">>> ".green(), app.finder.text()
+                ],
+            );
+        } else if app.grep.active() {
+            let (matches, files) = app.grep.counts();
+            let status = match (app.grep.done(), app.grep.truncated()) {
+                (false, _) => ", searching",
+                (true, true) => ", stopped at the limit",
+                (true, false) => "",
+            };
+            let title = format!("GREP {matches} matches in {files} files{status} ");
+            render!( window,
+                vec2(0, window.size().y - 6) =>
+                [
+                    title.as_str().green(), "─".repeat((window.size().x as usize).saturating_sub(title.chars().count())).green()
+                ],
+                vec2(0, window.size().y - 5) =>
+                [
+                    "enter".green(), " to reveal / ", "o".green(), "pen / ", "esc".red(), " to close"
+                ],
+            );
+        } else if app.viewer.active() {
+            let truncated = match app.viewer.truncated() {
+                true => ", truncated",
+                false => "",
+            };
+            let title = format!(
+                "VIEW {} ({}, {} lines{truncated}) {}% ",
+                app.viewer.path().display(),
+                app.viewer.syntax(),
+                app.viewer.len(),
+                app.viewer.percent()
+            );
+            render!( window,
+                vec2(0, window.size().y - 6) =>
+                [
+                    title.as_str().green(), "─".repeat((window.size().x as usize).saturating_sub(title.chars().count())).green()
+                ],
+                vec2(0, window.size().y - 5) =>
+                [
+                    "pgup".green(), " / ", "pgdn".green(), " to page / ", "esc".red(), " to close"
+                ],
+            );
+        } else if app.trash.active() {
+            let title = format!("TRASH {} ", app.trash.len());
+            render!( window,
+                vec2(0, window.size().y - 6) =>
+                [
+                    title.as_str().green(), "─".repeat((window.size().x as usize).saturating_sub(title.chars().count())).green()
+                ],
+                vec2(0, window.size().y - 5) =>
+                [
+                    "r".green(), "estore / ", "esc".red(), " to close"
+                ],
+            );
+        } else if app.jobs.list_active() {
+            let title = format!("JOBS {}/{} running ", app.jobs.running(), app.jobs.len());
+            render!( window,
+                vec2(0, window.size().y - 6) =>
+                [
+                    title.as_str().green(), "─".repeat((window.size().x as usize).saturating_sub(title.chars().count())).green()
+                ],
+                vec2(0, window.size().y - 5) =>
+                [
+                    "enter".green(), " to show / ", "c".green(), "ancel / ", "esc".red(), " to close"
+                ],
+            );
+        } else if let Some(conflict) = app.paste.as_ref().and_then(|x| x.conflict()) {
+            render!( window,
+                vec2(0, window.size().y - 6) =>
+                [
+                    conflict.display().to_string().red(), " already exists"
+                ],
+                vec2(0, window.size().y - 5) =>
+                [
+                    "o".green(), "verwrite / ", "s".green(), "kip / ", "r".green(), "ename / ", "esc".red(), " to cancel"
+                ],
+            );
+        } else if app.confirmation.active() {
             render!( window,
                 vec2(0, window.size().y - 6) =>
                 [
                     "Are you sure? ( ", "y".green(), " / ", "n".red(), " )?"
                 ]
             );
-        } else if input.active() {
+        } else if app.input.active() {
+            let title = match app.input.pending_event() {
+                Some(ExplorerEvent::Search) => format!("SEARCH {} ", explorer.match_count()),
+                Some(ExplorerEvent::FilterEntries) => format!("FILTER {} ", explorer.match_count()),
+                Some(ExplorerEvent::Grep) => "GREP ".to_string(),
+                _ => "INPUT ".to_string(),
+            };
             render!( window,
                 vec2(0, window.size().y - 6) =>
                 [
-                    "INPUT ".red(), "─".repeat(window.size().x as usize - 6).red()
+                    title.as_str().red(), "─".repeat((window.size().x as usize).saturating_sub(title.chars().count())).red()
                 ],
                 vec2(0, window.size().y - 5) =>
                 [
-                    ">>> ".red(), input.get_text()
+                    ">>> ".red(), app.input.get_text()
                 ],
             );
+        } else if let Some(job) = app.jobs.shown().filter(|_| app.log_string.is_empty()) {
+            // The shown job's output stays in the log until something else is logged.
+            let title = output::title(job);
+            let border = (window.size().x as usize).saturating_sub(title.content().chars().count());
+            render!( window,
+                vec2(0, window.size().y - 6) => [ title, "─".repeat(border) ],
+            );
+            let size = vec2(window.size().x, 5);
+            let loc = vec2(0, window.size().y - 5);
+            output::render_lines(&app.jobs, loc, size, window.buffer_mut());
         } else {
             render!( window,
                 vec2(0, window.size().y - 6) =>
                 [
-                    "Log ", "─".repeat(window.size().x as usize - 4)
+                    "Log ", "─".repeat((window.size().x as usize).saturating_sub(4))
                 ],
-                vec2(0, window.size().y - 5) => [ log_string ]
+                vec2(0, window.size().y - 5) => [ app.log_string ]
             );
         }
 
-        // Update the window over a long duration
-        window.update(Duration::from_secs(10))?;
+        // The expanded output is drawn over everything else.
+        if app.output_expanded {
+            output::render_popup(&app.jobs, window.buffer_mut());
+        }
+
+        // Update the window, waking up regularly to pick up file system changes.
+        // Wake up sooner while a preview is on its way or the finder is still ranking,
+        // so they show as soon as they're ready.
+        let poll = match app.preview.pending(&explorer) || app.finder.pending() {
+            true => Duration::from_millis(25),
+            false => Duration::from_millis(250),
+        };
+        window.update(poll)?;
     }
 
+    drop(window);
+    if let Some(output) = &mut pick_output {
+        for path in picked {
+            writeln!(output, "{}", path.display())?;
+        }
+    }
+
+    // Remember what was open, for the next time idex is opened here.
+    // Not being able to is no reason to fail once everything else is done.
+    if let Err(e) = explorer.session().save(explorer.root()) {
+        eprintln!("Failed to save the session: {e}");
+    }
     Ok(())
 }
+
+/// Returns the selected files to print in chooser mode, leaving out directories.
+fn pick(explorer: &Explorer) -> Vec<std::path::PathBuf> {
+    explorer
+        .selected_paths()
+        .into_iter()
+        .filter(|x| !x.is_dir())
+        .collect()
+}
@@ -0,0 +1,205 @@
+use ascii_forge::prelude::*;
+
+use crate::jobs::{Job, Jobs};
+
+/// Splits a line of command output into styled spans, following its ANSI color codes.
+/// Any other escape sequences and control characters are dropped.
+pub fn spans(text: &str, base: ContentStyle) -> Vec<StyledContent<String>> {
+    let mut spans = vec![];
+    let mut style = base;
+    let mut current = String::new();
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // Control sequences end with a byte in `@..=~`, only colors (`m`) are kept.
+                Some('[') => {
+                    let mut params = String::new();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            if c == 'm' {
+                                if !current.is_empty() {
+                                    spans.push(StyledContent::new(
+                                        style,
+                                        std::mem::take(&mut current),
+                                    ));
+                                }
+                                style = apply_sgr(style, base, &params);
+                            }
+                            break;
+                        }
+                        params.push(c);
+                    }
+                }
+                // Operating system commands, like titles and links, end with a bell or `ESC \`.
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\t' => current.push_str("    "),
+            c if c.is_control() => {}
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        spans.push(StyledContent::new(style, current));
+    }
+    spans
+}
+
+/// Applies the parameters of a select graphic rendition sequence (`ESC [ ... m`) to the style.
+fn apply_sgr(mut style: ContentStyle, base: ContentStyle, params: &str) -> ContentStyle {
+    let mut codes = params
+        .split([';', ':'])
+        .map(|x| x.parse::<u8>().unwrap_or(0));
+    while let Some(code) = codes.next() {
+        match code {
+            0 => style = base,
+            1 => style.attributes.set(Attribute::Bold),
+            2 => style.attributes.set(Attribute::Dim),
+            3 => style.attributes.set(Attribute::Italic),
+            4 => style.attributes.set(Attribute::Underlined),
+            7 => style.attributes.set(Attribute::Reverse),
+            22 => {
+                style.attributes.unset(Attribute::Bold);
+                style.attributes.unset(Attribute::Dim);
+            }
+            23 => style.attributes.unset(Attribute::Italic),
+            24 => style.attributes.unset(Attribute::Underlined),
+            27 => style.attributes.unset(Attribute::Reverse),
+            30..=37 => style.foreground_color = Some(Color::AnsiValue(code - 30)),
+            38 => style.foreground_color = extended_color(&mut codes),
+            39 => style.foreground_color = base.foreground_color,
+            40..=47 => style.background_color = Some(Color::AnsiValue(code - 40)),
+            48 => style.background_color = extended_color(&mut codes),
+            49 => style.background_color = base.background_color,
+            90..=97 => style.foreground_color = Some(Color::AnsiValue(code - 90 + 8)),
+            100..=107 => style.background_color = Some(Color::AnsiValue(code - 100 + 8)),
+            _ => {}
+        }
+    }
+    style
+}
+
+/// Reads a 256 color (`5;n`) or true color (`2;r;g;b`) from the rest of the codes.
+fn extended_color(codes: &mut impl Iterator<Item = u8>) -> Option<Color> {
+    match codes.next()? {
+        5 => Some(Color::AnsiValue(codes.next()?)),
+        2 => Some(Color::Rgb {
+            r: codes.next()?,
+            g: codes.next()?,
+            b: codes.next()?,
+        }),
+        _ => None,
+    }
+}
+
+/// Renders as many of the shown job's lines as fit in `size`, scrolled as far as the jobs are.
+/// Lines are cut off at the edge, and anything written to stderr is red unless it has its own colors.
+pub fn render_lines(jobs: &Jobs, loc: Vec2, size: Vec2, buffer: &mut Buffer) {
+    let Some(job) = jobs.shown() else {
+        return;
+    };
+    jobs.set_height(size.y as usize);
+
+    let end = job
+        .lines
+        .len()
+        .saturating_sub(jobs.scroll())
+        .max((size.y as usize).min(job.lines.len()));
+    let start = end.saturating_sub(size.y as usize);
+    for (i, line) in job.lines.range(start..end).enumerate() {
+        let base = match line.stderr {
+            true => ContentStyle::new().red(),
+            false => ContentStyle::new(),
+        };
+
+        let loc = vec2(loc.x, loc.y + i as u16);
+        render_clipped(&spans(&line.text, base), loc, size.x, buffer);
+    }
+}
+
+/// Renders spans on a single line, cutting them off after `width` columns.
+pub fn render_clipped(spans: &[StyledContent<String>], loc: Vec2, width: u16, buffer: &mut Buffer) {
+    // Rendering into a buffer of exactly the line's size keeps long lines from wrapping.
+    let mut line_buffer = Buffer::new(vec2(width, 1));
+    let mut line_loc = vec2(0, 0);
+    for span in spans {
+        let left = width.saturating_sub(line_loc.x) as usize;
+        if left == 0 {
+            break;
+        }
+        let text = span.content().chars().take(left).collect::<String>();
+        let len = text.chars().count() as u16;
+        render!(line_buffer, line_loc => [ StyledContent::new(*span.style(), text) ]);
+        line_loc.x += len;
+    }
+    render!(buffer, loc => [ line_buffer ]);
+}
+
+/// Returns the job's title, colored by whether it is running, succeeded, or failed.
+pub fn title(job: &Job) -> StyledContent<String> {
+    let title = format!(
+        "Job {}: {} ({}) ",
+        job.id,
+        job.command,
+        job.describe_status()
+    );
+    match (job.status.running(), job.succeeded()) {
+        (true, _) => title.yellow(),
+        (false, true) => title.green(),
+        (false, false) => title.red(),
+    }
+}
+
+/// Renders the shown job's output in a bordered popup over most of the buffer.
+pub fn render_popup(jobs: &Jobs, buffer: &mut Buffer) {
+    let Some(job) = jobs.shown() else {
+        return;
+    };
+    let size = buffer.size();
+    if size.x < 8 || size.y < 10 {
+        return;
+    }
+    let (x, y) = (2, 1);
+    let (width, height) = (size.x - 4, size.y - 8);
+    let inner = vec2(width - 2, height - 2);
+
+    // Clear the popup's area first, so the tree doesn't show through.
+    render!(buffer, vec2(x + 1, y + 1) => [ Buffer::new(inner) ]);
+
+    let title = title(job);
+    let title_len = title.content().chars().count() as u16;
+    let border = "─".repeat((width - 2).saturating_sub(title_len + 1) as usize);
+    let title = StyledContent::new(
+        *title.style(),
+        title
+            .content()
+            .chars()
+            .take(width as usize - 3)
+            .collect::<String>(),
+    );
+    render!(buffer, vec2(x, y) => [ "┌─", title, border, "┐" ]);
+    for i in 1..height - 1 {
+        render!(buffer,
+            vec2(x, y + i) => [ "│" ],
+            vec2(x + width - 1, y + i) => [ "│" ],
+        );
+    }
+    let hint = match job.dropped {
+        0 => " esc to close ".to_string(),
+        dropped => format!(" {dropped} lines dropped, esc to close "),
+    };
+    let hint = hint.chars().take(width as usize - 2).collect::<String>();
+    render!(buffer, vec2(x, y + height - 1) => [
+        "└", "─".repeat((width - 2).saturating_sub(hint.chars().count() as u16) as usize), hint, "┘"
+    ]);
+
+    render_lines(jobs, vec2(x + 1, y + 1), inner, buffer);
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+};
+
+use anyhow::bail;
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+
+use crate::{file_ops, journal::Journal};
+
+/// Entries that were yanked or cut, waiting to be pasted.
+#[derive(Clone)]
+pub struct Register {
+    pub paths: Vec<PathBuf>,
+    pub cut: bool,
+}
+
+struct PasteItem {
+    from: PathBuf,
+    to: PathBuf,
+    overwrite: bool,
+    // Where an overwritten entry is moved to, so the paste can be undone.
+    trash: Option<PathBuf>,
+}
+
+/// A paste that is waiting on the user to decide what to do with names that are already taken.
+pub struct PendingPaste {
+    cut: bool,
+    ready: Vec<PasteItem>,
+    conflicts: VecDeque<PasteItem>,
+    // Every destination an item that's ready will paste to, so no two items paste to the same one.
+    claimed: HashSet<PathBuf>,
+}
+
+pub enum ConflictEvent {
+    Cancel,
+    Resolved,
+}
+
+impl PendingPaste {
+    pub fn new(register: &Register, dir: &Path) -> anyhow::Result<Self> {
+        let mut pending = Self {
+            cut: register.cut,
+            ready: vec![],
+            conflicts: VecDeque::new(),
+            claimed: HashSet::new(),
+        };
+
+        for from in &register.paths {
+            let Some(name) = from.file_name() else {
+                continue;
+            };
+            let to = dir.join(name);
+            let item = PasteItem {
+                from: from.clone(),
+                to: to.clone(),
+                overwrite: false,
+                trash: None,
+            };
+
+            if *from == to {
+                // Pasting onto itself, so a cut is already done, and a copy needs a new name.
+                if !pending.cut {
+                    let to = file_ops::unique_name(&to, &pending.claimed);
+                    pending.ready(PasteItem { to, ..item });
+                }
+            } else if to.starts_with(from) || from.starts_with(&to) {
+                // Either the paste would never finish, or overwriting would move the source away.
+                bail!("can't paste {} into itself", from.display());
+            } else if to.symlink_metadata().is_ok() || pending.claimed.contains(&to) {
+                // Entries with the same name from different directories conflict with each other.
+                pending.conflicts.push_back(item);
+            } else {
+                pending.ready(item);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Queues the item to be pasted, claiming its destination.
+    fn ready(&mut self, item: PasteItem) {
+        self.claimed.insert(item.to.clone());
+        self.ready.push(item);
+    }
+
+    /// Returns the destination that is currently conflicting, if there is one.
+    pub fn conflict(&self) -> Option<&Path> {
+        self.conflicts.front().map(|x| x.to.as_path())
+    }
+
+    /// Handles a key for the current conflict: (o)verwrite, (s)kip, (r)ename, or escape to cancel.
+    pub fn handle(&mut self, k: KeyEvent) -> Option<ConflictEvent> {
+        if k.kind != KeyEventKind::Press {
+            return None;
+        }
+        let item = self.conflicts.pop_front()?;
+        match k.code {
+            KeyCode::Char('o') => self.ready(PasteItem {
+                overwrite: true,
+                ..item
+            }),
+            KeyCode::Char('s') => {}
+            KeyCode::Char('r') => {
+                let to = file_ops::unique_name(&item.to, &self.claimed);
+                self.ready(PasteItem { to, ..item })
+            }
+            KeyCode::Esc => return Some(ConflictEvent::Cancel),
+            _ => {
+                self.conflicts.push_front(item);
+                return None;
+            }
+        }
+        self.conflicts.is_empty().then_some(ConflictEvent::Resolved)
+    }
+
+    /// Starts pasting everything on a background thread.
+    pub fn start(mut self, journal: &mut Journal) -> anyhow::Result<PasteJob> {
+        for item in self.ready.iter_mut().filter(|x| x.overwrite) {
+            item.trash = Some(journal.slot(&item.to)?);
+        }
+
+        let (sender, receiver) = channel();
+        let cut = self.cut;
+        let items = self
+            .ready
+            .iter()
+            .map(|x| (x.from.clone(), x.to.clone(), x.trash.clone()))
+            .collect::<Vec<_>>();
+
+        thread::spawn(move || {
+            let total = items.iter().map(|x| file_ops::count(&x.0)).sum();
+            let mut done = 0;
+            for (completed, (from, to, trash)) in items.into_iter().enumerate() {
+                let res = (|| {
+                    if let Some(trash) = &trash {
+                        file_ops::move_path(&to, trash)?;
+                    }
+                    if cut {
+                        file_ops::move_path(&from, &to)
+                    } else {
+                        file_ops::copy_with_progress(&from, &to, &mut |path| {
+                            done += 1;
+                            let _ = sender.send(PasteStatus::Progress {
+                                done,
+                                total,
+                                path: path.to_path_buf(),
+                            });
+                        })
+                    }
+                })();
+                if let Err(e) = res {
+                    // Put back what the entry was about to overwrite, so a failed paste doesn't lose it.
+                    let trashed = trash.is_some_and(|trash| !restore(&trash, &to, cut));
+                    let _ = sender.send(PasteStatus::Failed(e.to_string(), completed, trashed));
+                    return;
+                }
+            }
+            let _ = sender.send(PasteStatus::Finished);
+        });
+
+        Ok(PasteJob {
+            cut,
+            items: self.ready,
+            receiver,
+        })
+    }
+}
+
+/// Moves an entry that was being overwritten back out of the trash, returning whether it could be.
+/// Whatever part of a copy made it to `to` is removed first, since the source is still there.
+fn restore(trash: &Path, to: &Path, cut: bool) -> bool {
+    if !cut && to.symlink_metadata().is_ok() && file_ops::remove(to).is_err() {
+        return false;
+    }
+    file_ops::move_path(trash, to).is_ok()
+}
+
+pub enum PasteStatus {
+    Progress {
+        done: usize,
+        total: usize,
+        path: PathBuf,
+    },
+    Finished,
+    // The error, how many entries were pasted before it happened, and whether the entry the
+    // failing one would have overwritten is still in the trash.
+    Failed(String, usize, bool),
+}
+
+/// A paste running on a background thread.
+pub struct PasteJob {
+    pub cut: bool,
+    items: Vec<PasteItem>,
+    receiver: Receiver<PasteStatus>,
+}
+
+impl PasteJob {
+    /// Returns the destination of every entry being pasted.
+    pub fn pasted(&self) -> Vec<PathBuf> {
+        self.items.iter().map(|x| x.to.clone()).collect()
+    }
+
+    /// Returns the moves done by the first `completed` entries, for the journal.
+    /// If `trashed` is set, the next entry's overwritten entry was moved to the trash too.
+    pub fn moves(
+        &self,
+        completed: usize,
+        trashed: bool,
+        journal: &mut Journal,
+    ) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+        let mut moves = vec![];
+        for item in self.items.iter().take(completed) {
+            if let Some(trash) = &item.trash {
+                moves.push((item.to.clone(), trash.clone()));
+            }
+            match self.cut {
+                true => moves.push((item.from.clone(), item.to.clone())),
+                false => moves.push(journal.created(&item.to)?),
+            }
+        }
+        if let Some(item) = self.items.get(completed).filter(|_| trashed) {
+            moves.extend(
+                item.trash
+                    .iter()
+                    .map(|trash| (item.to.clone(), trash.clone())),
+            );
+        }
+        Ok(moves)
+    }
+
+    /// Returns the latest status of the paste, if anything has happened since the last poll.
+    pub fn poll(&self) -> Option<PasteStatus> {
+        let mut latest = None;
+        loop {
+            match self.receiver.try_recv() {
+                Ok(status) => {
+                    let finished = !matches!(status, PasteStatus::Progress { .. });
+                    latest = Some(status);
+                    if finished {
+                        return latest;
+                    }
+                }
+                Err(TryRecvError::Empty) => return latest,
+                Err(TryRecvError::Disconnected) => {
+                    return latest.or(Some(PasteStatus::Failed(
+                        "paste stopped".to_string(),
+                        0,
+                        false,
+                    )))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{PasteStatus, PendingPaste, Register};
+    use crate::journal::Journal;
+
+    #[test]
+    fn failed_overwrites_keep_the_overwritten_entry() {
+        let dir = std::env::temp_dir().join(format!("idex-paste-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dest = dir.join("dest");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("notes.txt"), "original").unwrap();
+
+        // The source is gone by the time it's pasted, so copying it fails after the overwrite started.
+        let register = Register {
+            paths: vec![dir.join("notes.txt")],
+            cut: false,
+        };
+        let mut pending = PendingPaste::new(&register, &dest).unwrap();
+        pending.handle(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::NONE));
+        let mut journal = Journal::new();
+        let job = pending.start(&mut journal).unwrap();
+
+        let status = loop {
+            match job.poll() {
+                Some(PasteStatus::Progress { .. }) | None => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Some(status) => break status,
+            }
+        };
+        let contents = fs::read_to_string(dest.join("notes.txt"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(status, PasteStatus::Failed(_, 0, false)));
+        assert_eq!(contents.unwrap(), "original");
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, Metadata},
+    io::Read,
+    os::unix::{fs::MetadataExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ascii_forge::prelude::*;
+
+use crate::{
+    config::Config, dir_items::dir_listing, display, entry::Entry, explorer::Explorer, output,
+    sh::expand,
+};
+
+// How many previews are kept before the cache is cleared.
+const CACHE_SIZE: usize = 64;
+
+// How long the focus has to stay on an entry before it is previewed.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+// How often a previewer command is checked on, to kill it if it isn't needed anymore.
+const PREVIEWER_POLL: Duration = Duration::from_millis(20);
+
+enum PreviewContent {
+    Text {
+        lines: Vec<String>,
+        truncated: bool,
+    },
+    Binary,
+    // Only the first page of a large directory is read.
+    Dir {
+        entries: Vec<Entry>,
+        truncated: bool,
+    },
+    // The output of a configured previewer command.
+    Command(Vec<String>),
+    // Nothing is shown besides the metadata.
+    Other,
+    Loading,
+    Error(String),
+}
+
+struct CachedPreview {
+    modified: Option<SystemTime>,
+    metadata: Option<Metadata>,
+    content: PreviewContent,
+}
+
+/// A preview of the focused entry, shown to the right of the tree.
+/// Previews are only made once the focus settles on an entry, and are cached until it is modified.
+pub struct Preview {
+    pub enabled: bool,
+
+    path: Option<PathBuf>,
+    // When the focus moved onto the path, so moving through the tree quickly doesn't
+    // preview everything on the way.
+    focused_at: Instant,
+    cache: HashMap<PathBuf, CachedPreview>,
+
+    // Previews are made in the background, sending their content back along with the path.
+    sender: Sender<(PathBuf, PreviewContent)>,
+    receiver: Receiver<(PathBuf, PreviewContent)>,
+    // Bumped whenever the focus moves, telling previewer commands for the old path to stop.
+    generation: Arc<AtomicUsize>,
+}
+
+impl Preview {
+    pub fn new(config: &Config) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            enabled: config.preview.enabled,
+            path: None,
+            focused_at: Instant::now(),
+            cache: HashMap::new(),
+            sender,
+            receiver,
+            generation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns how many columns the preview takes up out of the window's width.
+    pub fn width(&self, config: &Config, window_width: u16) -> u16 {
+        match self.enabled {
+            true => (window_width as u32 * config.preview.width.min(100) as u32 / 100) as u16,
+            false => 0,
+        }
+    }
+
+    /// Returns whether the focused entry's preview is still to be made or on its way,
+    /// so the caller can update again sooner than it otherwise would.
+    pub fn pending(&self, explorer: &Explorer) -> bool {
+        let path = explorer.focused_path();
+        let cached = self.cache.get(&path).filter(|_| self.path == Some(path));
+        self.enabled && cached.is_none_or(|x| matches!(x.content, PreviewContent::Loading))
+    }
+
+    /// Starts making a preview of the focused entry, once it has been focused for a moment.
+    pub fn update(&mut self, explorer: &Explorer, config: &Config) {
+        for (path, content) in self.receiver.try_iter() {
+            if let Some(cached) = self.cache.get_mut(&path) {
+                cached.content = content;
+            }
+        }
+
+        if !self.enabled {
+            return;
+        }
+
+        let path = explorer.focused_path();
+        if self.path.as_ref() != Some(&path) {
+            // Whatever is still being made for the old path is given up on, to be made again later.
+            self.generation.fetch_add(1, Ordering::Relaxed);
+            if let Some(old) = self.path.take() {
+                if self
+                    .cache
+                    .get(&old)
+                    .is_some_and(|x| matches!(x.content, PreviewContent::Loading))
+                {
+                    self.cache.remove(&old);
+                }
+            }
+            self.path = Some(path.clone());
+            self.focused_at = Instant::now();
+        }
+
+        let metadata = fs::metadata(&path).ok();
+        let modified = metadata.as_ref().and_then(|x| x.modified().ok());
+        if self
+            .cache
+            .get(&path)
+            .is_some_and(|x| x.modified == modified)
+        {
+            return;
+        }
+        if self.focused_at.elapsed() < DEBOUNCE {
+            return;
+        }
+
+        if self.cache.len() >= CACHE_SIZE {
+            self.cache.clear();
+        }
+        let content = match &metadata {
+            Some(metadata) => self.start(&path, metadata, explorer, config),
+            None => PreviewContent::Error(format!("can't read {}", path.display())),
+        };
+        self.cache.insert(
+            path,
+            CachedPreview {
+                modified,
+                metadata,
+                content,
+            },
+        );
+    }
+
+    /// Starts making the preview in the background, returning what to show until it's done.
+    fn start(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        explorer: &Explorer,
+        config: &Config,
+    ) -> PreviewContent {
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let Some(previewer) = config.preview.find_command(&name) {
+            let command = expand(explorer, &previewer.command, &None);
+            let args = previewer
+                .args
+                .iter()
+                .map(|x| expand(explorer, x, &None))
+                .collect::<Vec<_>>();
+            run_previewer(
+                path.to_path_buf(),
+                command,
+                args,
+                config.preview.max_bytes,
+                self.sender.clone(),
+                self.generation.clone(),
+            );
+            return PreviewContent::Loading;
+        }
+        if !metadata.is_dir() && !metadata.is_file() {
+            return PreviewContent::Other;
+        }
+
+        let path = path.to_path_buf();
+        let is_dir = metadata.is_dir();
+        let len = metadata.len();
+        let filter = explorer.filter().clone();
+        let sort = *explorer.sort();
+        let max_bytes = config.preview.max_bytes;
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let content = match is_dir {
+                true => match dir_listing(&path, 1, &filter, &sort) {
+                    Ok(listing) => PreviewContent::Dir {
+                        entries: listing.entries,
+                        truncated: listing.rest.is_some(),
+                    },
+                    Err(e) => PreviewContent::Error(e.to_string()),
+                },
+                false => match read_start(&path, max_bytes) {
+                    Ok(bytes) if bytes.contains(&0) => PreviewContent::Binary,
+                    Ok(bytes) => PreviewContent::Text {
+                        lines: String::from_utf8_lossy(&bytes)
+                            .lines()
+                            .map(str::to_string)
+                            .collect(),
+                        truncated: len > bytes.len() as u64,
+                    },
+                    Err(e) => PreviewContent::Error(e.to_string()),
+                },
+            };
+            let _ = sender.send((path, content));
+        });
+        PreviewContent::Loading
+    }
+
+    /// Renders the preview into the area at `loc`, with a separator on its left.
+    pub fn render_at(&self, loc: Vec2, size: Vec2, buffer: &mut Buffer, config: &Config) {
+        for y in 0..size.y {
+            render!(buffer, vec2(loc.x, loc.y + y) => [ "│".dark_grey() ]);
+        }
+        let (loc, width) = (vec2(loc.x + 2, loc.y), size.x.saturating_sub(2));
+
+        let Some(cached) = self.path.as_ref().and_then(|x| self.cache.get(x)) else {
+            return;
+        };
+        let line = |text: String| vec![text.stylize()];
+
+        let mut header = match &cached.metadata {
+            Some(metadata) => describe_metadata(metadata),
+            None => String::new(),
+        };
+        let mut body = vec![];
+        match &cached.content {
+            PreviewContent::Text { lines, truncated } => {
+                let digits = lines.len().max(1).ilog10() as usize + 1;
+                let visible = size.y.saturating_sub(1) as usize;
+                for (i, text) in lines.iter().take(visible).enumerate() {
+                    let mut spans = vec![format!("{:>digits$} ", i + 1).dark_grey()];
+                    spans.extend(output::spans(text, ContentStyle::new()));
+                    body.push(spans);
+                }
+                if *truncated {
+                    header.push_str("  (truncated)");
+                }
+            }
+            PreviewContent::Binary => body.push(line("Binary file".to_string())),
+            PreviewContent::Dir { entries, truncated } => {
+                header = match truncated {
+                    true => format!("{header}  {}+ entries", entries.len()),
+                    false => format!("{header}  {} entries", entries.len()),
+                };
+                // Children are rendered just like they are in the tree.
+                let mut entry_loc = vec2(loc.x, loc.y + 1);
+                let mut entry_buffer = Buffer::new(vec2(width, 1));
+                for entry in entries.iter().take(size.y.saturating_sub(1) as usize) {
+                    entry_buffer.clear();
+                    entry.render(
+                        vec2(0, 0),
+                        &mut entry_buffer,
+                        false,
+                        false,
+                        None,
+                        None,
+                        config,
+                    );
+                    render!(buffer, entry_loc => [ entry_buffer ]);
+                    entry_loc.y += 1;
+                }
+            }
+            PreviewContent::Command(lines) => {
+                for text in lines {
+                    body.push(output::spans(text, ContentStyle::new()));
+                }
+            }
+            PreviewContent::Other => {}
+            PreviewContent::Loading => body.push(line("Loading...".to_string())),
+            PreviewContent::Error(e) => body.push(vec![format!("Error: {e}").red()]),
+        }
+
+        output::render_clipped(&[header.dark_grey()], loc, width, buffer);
+        for (i, spans) in body
+            .iter()
+            .take(size.y.saturating_sub(1) as usize)
+            .enumerate()
+        {
+            output::render_clipped(spans, vec2(loc.x, loc.y + 1 + i as u16), width, buffer);
+        }
+    }
+}
+
+/// Reads up to `max` bytes from the start of a file.
+fn read_start(path: &Path, max: u64) -> std::io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    File::open(path)?.take(max).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Runs a previewer command in the background, killing it if the focus moves on before it's done.
+/// Only the first `max_bytes` of each of its outputs are read, it's killed once it writes more.
+fn run_previewer(
+    path: PathBuf,
+    command: String,
+    args: Vec<String>,
+    max_bytes: u64,
+    sender: Sender<(PathBuf, PreviewContent)>,
+    generation: Arc<AtomicUsize>,
+) {
+    let started = generation.load(Ordering::Relaxed);
+    thread::spawn(move || {
+        let spawned = Command::new(&command)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                let content = PreviewContent::Error(format!("failed to run {command}: {e}"));
+                let _ = sender.send((path, content));
+                return;
+            }
+        };
+
+        // The output is read on threads of its own, so the command can be killed while it runs.
+        let full = Arc::new(AtomicBool::new(false));
+        let stdout = child
+            .stdout
+            .take()
+            .map(|x| read_start_of(x, max_bytes, full.clone()));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|x| read_start_of(x, max_bytes, full.clone()));
+        while let Ok(None) = child.try_wait() {
+            let moved_on = generation.load(Ordering::Relaxed) != started;
+            if moved_on || full.load(Ordering::Relaxed) {
+                // The whole group is killed, in case the command is a script that started others.
+                // SAFETY: `kill` has no memory safety requirements, the group id is the child's pid.
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                let _ = child.wait();
+                if moved_on {
+                    return;
+                }
+                // What it wrote before it filled up the preview is still shown.
+                break;
+            }
+            thread::sleep(PREVIEWER_POLL);
+        }
+
+        let output = |x: Option<thread::JoinHandle<Vec<u8>>>| {
+            x.and_then(|x| x.join().ok()).unwrap_or_default()
+        };
+        let (stdout, stderr) = (output(stdout), output(stderr));
+        let content = PreviewContent::Command(
+            String::from_utf8_lossy(&stdout)
+                .lines()
+                .chain(String::from_utf8_lossy(&stderr).lines())
+                .map(str::to_string)
+                .collect(),
+        );
+        let _ = sender.send((path, content));
+    });
+}
+
+/// Reads up to `max` bytes from the pipe on another thread, setting `full` if there was more.
+fn read_start_of(
+    pipe: impl Read + Send + 'static,
+    max: u64,
+    full: Arc<AtomicBool>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = vec![];
+        let _ = pipe.take(max).read_to_end(&mut bytes);
+        if bytes.len() as u64 >= max {
+            full.store(true, Ordering::Relaxed);
+        }
+        bytes
+    })
+}
+
+/// Describes the entry's size, modification time, and permissions.
+fn describe_metadata(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .map(display::local_time)
+        .unwrap_or_default();
+    format!(
+        "{}  {}  {}",
+        display::permissions(metadata.mode()),
+        display::human_size(metadata.len()),
+        modified
+    )
+}
@@ -0,0 +1,62 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::config::SortConfig;
+
+/// What the explorer looked like when idex last quit in a root, so it can be picked up again.
+/// Paths are relative to the root.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    // Parents come before their children, so they can be expanded in order.
+    pub expanded: Vec<PathBuf>,
+    pub selected: Option<PathBuf>,
+    pub scroll: usize,
+
+    pub filter_enabled: Option<bool>,
+    pub show_hidden: Option<bool>,
+    // Tables have to come after plain values in TOML.
+    pub sort: Option<SortConfig>,
+}
+
+impl Session {
+    /// Returns the session saved for the root, if there is one that can be read.
+    pub fn load(root: &Path) -> Option<Self> {
+        let text = fs::read_to_string(path(root)?).ok()?;
+        toml::from_str(&text).ok()
+    }
+
+    pub fn save(&self, root: &Path) -> anyhow::Result<()> {
+        let path = path(root).ok_or(anyhow!("no state directory to keep sessions in"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Returns where the root's session is kept, named after the root's directory and a hash of its
+/// whole path, like `$XDG_STATE_HOME/idex/project-8c3f0e5d2a1b9f47.toml`.
+/// The hash keeps names unique and short, however long the path is.
+fn path(root: &Path) -> Option<PathBuf> {
+    let dir = dirs::state_dir().or_else(dirs::data_local_dir)?;
+    let name = root
+        .file_name()
+        .map(|x| x.to_string_lossy().chars().take(32).collect::<String>())
+        .unwrap_or_default();
+    let hash = fnv1a(root.as_os_str().as_encoded_bytes());
+    Some(dir.join("idex").join(format!("{name}-{hash:016x}.toml")))
+}
+
+/// The 64 bit FNV-1a hash, which unlike the standard library's hasher stays the same between builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, x| {
+        (hash ^ *x as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -1,31 +1,610 @@
-use std::process::Command;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
 
-use crate::explorer::Explorer;
+use anyhow::anyhow;
 
-pub fn handle_sh(
+use crate::{explorer::Explorer, journal::Snapshot};
+
+/// Expands `~`, environment variables, and the explorer's variables in the text.
+pub fn expand(explorer: &Explorer, text: &str, input: &Option<String>) -> String {
+    expand_with(explorer, text, input, &Target::current(explorer))
+}
+
+/// The entries one run of a command is about.
+struct Target {
+    // The paths for `$SELECTED`.
+    selected: Vec<PathBuf>,
+    // The entry `$FOCUSED` and the variables taken from it describe.
+    focused: PathBuf,
+}
+
+impl Target {
+    /// Targets what is selected and focused in the explorer.
+    fn current(explorer: &Explorer) -> Self {
+        Self {
+            selected: explorer.selected_paths(),
+            focused: explorer.focused_path(),
+        }
+    }
+}
+
+/// Expands the text for a target, with the paths of `$SELECTED` joined by spaces.
+fn expand_with(explorer: &Explorer, text: &str, input: &Option<String>, target: &Target) -> String {
+    shellexpand::full_with_context_no_errors(
+        text,
+        || dirs::home_dir().map(|x| x.into_os_string().into_string().unwrap()),
+        |name| match variable(explorer, name, input, target) {
+            Some(values) => Some(values.join(" ")),
+            None => std::env::var(name).ok(),
+        },
+    )
+    .to_string()
+}
+
+/// Replaces the explorer's variables in a command for the shell with references to the
+/// environment variables they are exported as. Anything else, like environment variables and `~`,
+/// is left for the shell to expand.
+fn expand_quoted(
     explorer: &Explorer,
-    command: String,
-    mut args: Vec<String>,
-    log_string: &mut String,
-    input: Option<String>,
-) {
-    args.iter_mut().for_each(|x| {
-        *x = shellexpand::full_with_context_no_errors(
-            x,
-            || dirs::home_dir().map(|x| x.into_os_string().into_string().unwrap()),
-            |val| match val {
-                "FOCUSED" => Some(
-                    explorer
-                        .focused_path()
-                        .into_os_string()
-                        .into_string()
-                        .unwrap(),
-                ),
-                "INPUT" => input.clone(),
-                _ => None,
+    text: &str,
+    input: &Option<String>,
+    target: &Target,
+) -> String {
+    substitute_quoted(text, |name| {
+        variable(explorer, name, input, target).is_some()
+    })
+}
+
+/// Replaces variables in a command for the shell with references to the `IDEX_` environment
+/// variables their values are exported as, and `$SELECTED` with the positional parameters its
+/// paths are passed as. The values never become part of the command, so a name can't run anything.
+/// References are written as `${IDEX_NAME+"$IDEX_NAME"}`, which the shell keeps as one word whether
+/// it is bare, in double quotes, in a command substitution or in a heredoc, so all that's followed
+/// is where the shell doesn't expand variables: single quotes, comments, arithmetic, and heredocs
+/// with a quoted delimiter.
+fn substitute_quoted(text: &str, known: impl Fn(&str) -> bool) -> String {
+    let mut expanded = String::new();
+    let mut double = false;
+    // Heredocs started on the current line, whose bodies follow it.
+    let mut heredocs: Vec<Heredoc> = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let len = match c {
+            '\'' if !double => rest.find('\'').map_or(rest.len(), |x| x + 1),
+            '"' => {
+                double = !double;
+                0
+            }
+            // A backslash keeps the next character as it is.
+            '\\' => rest.chars().next().map_or(0, char::len_utf8),
+            // A comment starts with a `#` at the start of a word, and goes on to the end of the line.
+            '#' if !double
+                && expanded
+                    .chars()
+                    .last()
+                    .is_none_or(|x| " \t\n;&|(".contains(x)) =>
+            {
+                rest.find('\n').unwrap_or(rest.len())
+            }
+            '$' => match dollar(rest, &known) {
+                Some((replaced, len)) => {
+                    expanded.push_str(&replaced);
+                    rest = &rest[len..];
+                    continue;
+                }
+                None => 0,
             },
-        )
-        .to_string();
-    });
-    *log_string = format!("{:?}", Command::new(command).args(args).output());
+            // `<<<` is a herestring, which has no body.
+            '<' if !double && rest.starts_with("<<") => 2,
+            '<' if !double && rest.starts_with('<') => {
+                let (heredoc, len) = Heredoc::parse(&rest[1..]);
+                heredocs.extend(heredoc);
+                len + 1
+            }
+            '\n' if !double => {
+                expanded.push(c);
+                for heredoc in heredocs.drain(..) {
+                    rest = heredoc.body(rest, &known, &mut expanded);
+                }
+                continue;
+            }
+            _ => 0,
+        };
+        expanded.push(c);
+        expanded.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    expanded
+}
+
+/// Returns what a `$` and the `rest` after it are replaced with, and how much of `rest` that takes.
+fn dollar(rest: &str, known: &impl Fn(&str) -> bool) -> Option<(String, usize)> {
+    if rest.starts_with("((") {
+        // Arithmetic evaluates the text of the variables in it again, which can run commands,
+        // so nothing in it is replaced.
+        let mut open = 0;
+        let len = rest
+            .char_indices()
+            .find_map(|(i, c)| {
+                match c {
+                    '(' => open += 1,
+                    ')' => open -= 1,
+                    _ => return None,
+                }
+                (open == 0).then_some(i + 1)
+            })
+            .unwrap_or(rest.len());
+        return Some((format!("${}", &rest[..len]), len));
+    }
+
+    // Both `$NAME` and `${NAME}` are supported.
+    let (name, len) = match rest.strip_prefix('{').and_then(|x| x.split_once('}')) {
+        Some((name, _)) => (name, name.len() + 2),
+        None => {
+            let len = rest
+                .find(|x: char| !(x.is_ascii_alphanumeric() || x == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..len], len)
+        }
+    };
+    if name.is_empty() || !known(name) {
+        return None;
+    }
+    let reference = match name {
+        "SELECTED" => "${1+\"$@\"}".to_string(),
+        _ => format!("${{IDEX_{name}+\"$IDEX_{name}\"}}"),
+    };
+    Some((reference, len))
+}
+
+/// A heredoc whose body starts on the line after it.
+struct Heredoc {
+    delimiter: String,
+    // Quoting any of the delimiter leaves the body as it is.
+    quoted: bool,
+    // With `<<-`, tabs at the start of the body's lines are taken out.
+    strip_tabs: bool,
+}
+
+impl Heredoc {
+    /// Reads the heredoc that the `rest` after a `<<` starts, and how much of `rest` it takes.
+    fn parse(rest: &str) -> (Option<Self>, usize) {
+        let strip_tabs = rest.starts_with('-');
+        let start = strip_tabs as usize;
+        let blanks = rest[start..].len() - rest[start..].trim_start_matches([' ', '\t']).len();
+        let word = &rest[start + blanks..];
+        let word = &word[..word
+            .find(|x: char| x.is_whitespace() || ";&|<>()".contains(x))
+            .unwrap_or(word.len())];
+
+        let heredoc = Self {
+            delimiter: word.replace(['\'', '"', '\\'], ""),
+            quoted: word.contains(['\'', '"', '\\']),
+            strip_tabs,
+        };
+        let heredoc = (!heredoc.delimiter.is_empty()).then_some(heredoc);
+        (heredoc, start + blanks + word.len())
+    }
+
+    /// Adds the body, up to and including its delimiter line, from the start of `rest`,
+    /// returning what comes after it.
+    fn body<'a>(
+        &self,
+        mut rest: &'a str,
+        known: &impl Fn(&str) -> bool,
+        expanded: &mut String,
+    ) -> &'a str {
+        while !rest.is_empty() {
+            let len = rest.find('\n').map_or(rest.len(), |x| x + 1);
+            let (line, next) = rest.split_at(len);
+            rest = next;
+
+            let text = line.trim_end_matches('\n');
+            let text = match self.strip_tabs {
+                true => text.trim_start_matches('\t'),
+                false => text,
+            };
+            if text == self.delimiter {
+                expanded.push_str(line);
+                break;
+            }
+            if self.quoted {
+                expanded.push_str(line);
+                continue;
+            }
+            // Only backslashes and variables mean anything in the body.
+            let mut line = line;
+            while let Some(idx) = line.find(['\\', '$']) {
+                expanded.push_str(&line[..idx]);
+                let c = &line[idx..idx + 1];
+                line = &line[idx + 1..];
+                let len = match c {
+                    "\\" => line.chars().next().map_or(0, char::len_utf8),
+                    _ => match dollar(line, known) {
+                        Some((replaced, len)) => {
+                            expanded.push_str(&replaced);
+                            line = &line[len..];
+                            continue;
+                        }
+                        None => 0,
+                    },
+                };
+                expanded.push_str(c);
+                expanded.push_str(&line[..len]);
+                line = &line[len..];
+            }
+            expanded.push_str(line);
+        }
+        rest
+    }
+}
+
+/// Shells that take the same quotes as `sh`, which the values are quoted for.
+const POSIX_SHELLS: [&str; 7] = ["sh", "bash", "dash", "zsh", "ksh", "mksh", "ash"];
+
+/// Returns the user's `$SHELL` if it quotes like `sh`, or `sh` for ones like fish and nu.
+fn shell() -> String {
+    std::env::var("SHELL")
+        .ok()
+        .filter(|x| {
+            let name = Path::new(x).file_name().and_then(|x| x.to_str());
+            name.is_some_and(|x| POSIX_SHELLS.contains(&x))
+        })
+        .unwrap_or("sh".to_string())
+}
+
+/// Every variable the explorer provides to commands, see the README for what each one holds.
+const VARIABLES: [&str; 12] = [
+    "FOCUSED", "SELECTED", "INPUT", "ROOT", "DIR", "PARENT", "NAME", "STEM", "EXT", "RELATIVE",
+    "TYPE", "LINE",
+];
+
+/// Returns the values of one of the explorer's variables, `$SELECTED` having one per path.
+fn variable(
+    explorer: &Explorer,
+    name: &str,
+    input: &Option<String>,
+    target: &Target,
+) -> Option<Vec<String>> {
+    let focused = &target.focused;
+    // Only the explorer knows what a focused link points to, anything else is looked up.
+    let is_file = match *focused == explorer.focused_path() {
+        true => explorer.is_file(),
+        false => !focused.is_dir(),
+    };
+    let os_string = |x: Option<&std::ffi::OsStr>| x.map(|x| x.to_string_lossy().into_owned());
+    let value = match name {
+        "FOCUSED" => path_string(focused),
+        "SELECTED" => return Some(target.selected.iter().map(|x| path_string(x)).collect()),
+        "INPUT" => input.clone()?,
+        "ROOT" => path_string(explorer.root()),
+        "DIR" => match is_file {
+            true => path_string(focused.parent().unwrap_or(focused)),
+            false => path_string(focused),
+        },
+        "PARENT" => path_string(focused.parent().unwrap_or(focused)),
+        "NAME" => os_string(focused.file_name()).unwrap_or_default(),
+        "STEM" => os_string(focused.file_stem()).unwrap_or_default(),
+        "EXT" => os_string(focused.extension()).unwrap_or_default(),
+        "RELATIVE" => path_string(focused.strip_prefix(explorer.root()).unwrap_or(focused)),
+        "TYPE" => match is_file {
+            true => "file".to_string(),
+            false => "dir".to_string(),
+        },
+        // A line is only ever picked for the focused entry.
+        "LINE" => match *focused == explorer.focused_path() {
+            true => explorer.focused_line().to_string(),
+            false => "1".to_string(),
+        },
+        _ => return None,
+    };
+    Some(vec![value])
+}
+
+/// Returns the explorer's variables as environment variables for a command, prefixed with `IDEX_`.
+/// `IDEX_SELECTED` has one path per line.
+fn environment(
+    explorer: &Explorer,
+    input: &Option<String>,
+    target: &Target,
+) -> Vec<(String, String)> {
+    VARIABLES
+        .iter()
+        .filter_map(|name| {
+            let values = variable(explorer, name, input, target)?;
+            Some((format!("IDEX_{name}"), values.join("\n")))
+        })
+        .collect()
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// The arguments and environment of one run of a command.
+pub struct Run {
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+}
+
+/// A command with its arguments expanded for each run, ready to be started.
+pub struct PreparedSh {
+    pub program: String,
+    pub runs: Vec<Run>,
+    // What the command is shown as in the log, the jobs list, and the journal.
+    pub command: String,
+    // The directories as they were before the command ran, so its changes can be journaled.
+    pub snapshot: Snapshot,
+}
+
+impl PreparedSh {
+    /// Expands the command and its arguments, once per selected entry if `each` is set.
+    pub fn new(
+        explorer: &Explorer,
+        command: &str,
+        args: &[String],
+        each: bool,
+        input: Option<String>,
+    ) -> Self {
+        let selected = explorer.selected_paths();
+        let snapshot = snapshot(explorer, &selected);
+
+        let runs = runs(explorer, selected, each)
+            .into_iter()
+            .map(|target| {
+                let mut expanded = vec![];
+                for arg in args {
+                    // An argument of just `$SELECTED` becomes one argument per path, so spaces in names are kept.
+                    if arg == "$SELECTED" {
+                        expanded.extend(target.selected.iter().map(|x| path_string(x)));
+                    } else {
+                        expanded.push(expand_with(explorer, arg, &input, &target));
+                    }
+                }
+                Run {
+                    args: expanded,
+                    env: environment(explorer, &input, &target),
+                }
+            })
+            .collect();
+
+        let program = expand(explorer, command, &input);
+        Self {
+            command: program.clone(),
+            program,
+            runs,
+            snapshot,
+        }
+    }
+
+    /// Prepares a command string to be run by a POSIX `$SHELL -c`, once per selected entry if `each` is set.
+    pub fn shell(explorer: &Explorer, command: &str, each: bool, input: Option<String>) -> Self {
+        let selected = explorer.selected_paths();
+        let snapshot = snapshot(explorer, &selected);
+
+        let runs = runs(explorer, selected, each)
+            .into_iter()
+            .map(|target| {
+                // The selected paths are the positional parameters, after the shell's name as `$0`.
+                let mut args = vec![
+                    "-c".to_string(),
+                    expand_quoted(explorer, command, &input, &target),
+                    "idex".to_string(),
+                ];
+                args.extend(target.selected.iter().map(|x| path_string(x)));
+                Run {
+                    args,
+                    env: environment(explorer, &input, &target),
+                }
+            })
+            .collect();
+
+        Self {
+            program: shell(),
+            runs,
+            command: command.to_string(),
+            snapshot,
+        }
+    }
+
+    /// Runs the command attached to the terminal, waiting for every run to exit.
+    /// The window has to be restored first, so the program can take over the screen.
+    pub fn run_interactive(self, explorer: &mut Explorer) -> anyhow::Result<String> {
+        let mut status = None;
+        for run in &self.runs {
+            let exit = Command::new(&self.program)
+                .args(&run.args)
+                .envs(run.env.iter().cloned())
+                .status()
+                .map_err(|e| anyhow!("failed to run {}: {e}", self.program))?;
+            if status.is_none_or(|x: ExitStatus| x.success()) {
+                status = Some(exit);
+            }
+        }
+
+        let moves = self.snapshot.changes(explorer.journal_mut())?;
+        explorer.record(format!("Run {}", self.command), moves);
+        Ok(match status.and_then(|x| x.code()) {
+            Some(code) => format!("Ran {} (exit {code})", self.command),
+            None => format!("Ran {}", self.command),
+        })
+    }
+}
+
+/// Snapshots the directories a command is most likely to touch, so what it did can be undone.
+fn snapshot(explorer: &Explorer, selected: &[PathBuf]) -> Snapshot {
+    let mut dirs = HashSet::from([explorer.root().to_path_buf(), explorer.focused_dir()]);
+    dirs.extend(
+        selected
+            .iter()
+            .filter_map(|x| x.parent())
+            .map(Path::to_path_buf),
+    );
+    Snapshot::take(dirs, explorer.journal())
+}
+
+/// Splits the selection into the target of each run.
+/// When running once per entry, every variable describes that entry, as if it were focused.
+fn runs(explorer: &Explorer, selected: Vec<PathBuf>, each: bool) -> Vec<Target> {
+    match each {
+        true => selected
+            .into_iter()
+            .map(|x| Target {
+                selected: vec![x.clone()],
+                focused: x,
+            })
+            .collect(),
+        false => vec![Target {
+            selected,
+            focused: explorer.focused_path(),
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::substitute_quoted;
+
+    // Names that would run something, or split into several words, if they weren't quoted.
+    const NAMES: [&str; 6] = [
+        "it's",
+        "say \"hi\"",
+        "$(echo pwned)",
+        "`echo pwned`",
+        "two\nlines",
+        "back\\slash $HOME",
+    ];
+
+    /// Runs the command with `$FOCUSED` set to the value, and `$SELECTED` to the selected paths.
+    fn run_selected(command: &str, value: &str, selected: &[&str]) -> String {
+        let command = substitute_quoted(command, |name| name == "FOCUSED" || name == "SELECTED");
+        let output = Command::new("sh")
+            .args(["-c", &command, "idex"])
+            .args(selected)
+            .env("IDEX_FOCUSED", value)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn run(command: &str, value: &str) -> String {
+        run_selected(command, value, &[])
+    }
+
+    #[test]
+    fn bare_values_are_one_word() {
+        for name in NAMES {
+            assert_eq!(run("printf '%s|' $FOCUSED", name), format!("{name}|"));
+        }
+    }
+
+    #[test]
+    fn values_in_double_quotes_are_not_expanded() {
+        for name in NAMES {
+            assert_eq!(run("printf '%s|' \"$FOCUSED\"", name), format!("{name}|"));
+            assert_eq!(
+                run("printf '%s|' \"<${FOCUSED}>\"", name),
+                format!("<{name}>|")
+            );
+        }
+    }
+
+    #[test]
+    fn values_in_command_substitutions_are_quoted() {
+        for name in NAMES {
+            assert_eq!(
+                run("printf '%s|' \"$(printf %s $FOCUSED)\"", name),
+                format!("{name}|")
+            );
+            assert_eq!(
+                run("printf '%s|' \"`printf %s $FOCUSED`\"", name),
+                format!("{name}|")
+            );
+            assert_eq!(
+                run("printf '%s|' \"`printf %s \"$FOCUSED\"`\"", name),
+                format!("{name}|")
+            );
+            assert_eq!(
+                run("printf '%s|' \"$( (printf %s $FOCUSED) )$((1 + 1))\"", name),
+                format!("{name}2|")
+            );
+        }
+    }
+
+    #[test]
+    fn case_patterns_and_comments_keep_values_quoted() {
+        let command = "# it's $FOCUSED\nprintf '%s|' \"$(case $FOCUSED in (x) ;; *) printf %s $FOCUSED ;; esac)\" # $FOCUSED's";
+        for name in NAMES {
+            assert_eq!(run(command, name), format!("{name}|"));
+        }
+        assert_eq!(
+            substitute_quoted("echo a#$FOCUSED $# # '$FOCUSED", |_| true),
+            "echo a#${IDEX_FOCUSED+\"$IDEX_FOCUSED\"} $# # '$FOCUSED"
+        );
+    }
+
+    #[test]
+    fn heredocs_keep_values_whole() {
+        for name in NAMES {
+            assert_eq!(
+                run("cat <<EOF\n<$FOCUSED>\nEOF\nprintf '%s|' $FOCUSED", name),
+                format!("<{name}>\n{name}|")
+            );
+            assert_eq!(
+                run(
+                    "cat <<-'EOF'; printf '%s|' $FOCUSED\n\t<$FOCUSED>\n\tEOF",
+                    name
+                ),
+                format!("<$FOCUSED>\n{name}|")
+            );
+        }
+    }
+
+    #[test]
+    fn herestrings_and_arithmetic_are_not_heredocs() {
+        // `sh` may not have herestrings, so that one is only substituted.
+        assert_eq!(
+            substitute_quoted("cat <<<x; rm $FOCUSED", |_| true),
+            "cat <<<x; rm ${IDEX_FOCUSED+\"$IDEX_FOCUSED\"}"
+        );
+        for name in NAMES {
+            assert_eq!(
+                run("printf '%s|' $((1<<2)) $FOCUSED", name),
+                format!("4|{name}|")
+            );
+        }
+    }
+
+    #[test]
+    fn single_quotes_and_escapes_are_left_alone() {
+        assert_eq!(
+            substitute_quoted("echo '$FOCUSED' \\$FOCUSED $FOCUSED", |_| true),
+            "echo '$FOCUSED' \\$FOCUSED ${IDEX_FOCUSED+\"$IDEX_FOCUSED\"}"
+        );
+    }
+
+    #[test]
+    fn unknown_variables_are_left_for_the_shell() {
+        assert_eq!(
+            substitute_quoted("echo \"$HOME\" $FOCUSED", |name| name == "FOCUSED"),
+            "echo \"$HOME\" ${IDEX_FOCUSED+\"$IDEX_FOCUSED\"}"
+        );
+    }
+
+    #[test]
+    fn selected_paths_are_separate_words() {
+        let selected = ["a b", "c'd", "$(echo pwned)"];
+        for command in ["printf '%s|' $SELECTED", "printf '%s|' \"$SELECTED\""] {
+            assert_eq!(
+                run_selected(command, "", &selected),
+                "a b|c'd|$(echo pwned)|"
+            );
+        }
+    }
 }
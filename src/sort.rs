@@ -0,0 +1,217 @@
+use std::{cmp::Ordering, fmt::Display, iter::Peekable, str::Chars};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::SortConfig, entry::Entry};
+
+/// What entries in the same directory are ordered by.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortMode {
+    // Byte-wise, so uppercase names come before lowercase ones.
+    #[default]
+    Name,
+    // Runs of digits are compared as numbers, so `v2` comes before `v10`.
+    Natural,
+    CaseInsensitive,
+    // Newest first.
+    Modified,
+    // Largest first.
+    Size,
+    Extension,
+}
+
+impl SortMode {
+    /// Returns the mode after this one, wrapping around.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Name => Self::Natural,
+            Self::Natural => Self::CaseInsensitive,
+            Self::CaseInsensitive => Self::Modified,
+            Self::Modified => Self::Size,
+            Self::Size => Self::Extension,
+            Self::Extension => Self::Name,
+        }
+    }
+}
+
+impl Display for SortMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Name => "name",
+            Self::Natural => "natural name",
+            Self::CaseInsensitive => "case insensitive name",
+            Self::Modified => "modification time",
+            Self::Size => "size",
+            Self::Extension => "extension",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl SortConfig {
+    /// Compares two entries in the same directory, ties being broken by name.
+    pub fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        let by_type = match self.dirs_first {
+            true => b.is_dir().cmp(&a.is_dir()),
+            false => Ordering::Equal,
+        };
+        let by_mode = match self.mode {
+            SortMode::Name => Ordering::Equal,
+            SortMode::Natural => natural_cmp(&a.file_name, &b.file_name),
+            SortMode::CaseInsensitive => {
+                a.file_name.to_lowercase().cmp(&b.file_name.to_lowercase())
+            }
+            SortMode::Modified => b.modified.cmp(&a.modified),
+            SortMode::Size => size(b).cmp(&size(a)),
+            SortMode::Extension => extension(a).cmp(extension(b)),
+        }
+        .then_with(|| a.file_name.cmp(&b.file_name));
+
+        // Directories stay first when reversed, only the order within each group flips.
+        by_type.then(match self.reverse {
+            true => by_mode.reverse(),
+            false => by_mode,
+        })
+    }
+
+    /// Describes the order for the log, like `size (reversed), directories first`.
+    pub fn describe(&self) -> String {
+        let mut text = self.mode.to_string();
+        if self.reverse {
+            text.push_str(" (reversed)");
+        }
+        match self.dirs_first {
+            true => text.push_str(", directories first"),
+            false => text.push_str(", directories mixed in"),
+        }
+        text
+    }
+}
+
+/// Returns the entry's size, or the size of everything inside of a directory once it's worked out.
+fn size(entry: &Entry) -> u64 {
+    match entry.is_dir() {
+        true => entry.dir_size.unwrap_or(entry.size),
+        false => entry.size,
+    }
+}
+
+/// Returns the part of the name after the last dot, ignoring a leading dot.
+fn extension(entry: &Entry) -> &str {
+    match entry.file_name.rfind('.') {
+        Some(idx) if idx > 0 => &entry.file_name[idx + 1..],
+        _ => "",
+    }
+}
+
+/// Compares names with runs of digits compared by their value, like `file2` before `file10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let ord = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (digits(&mut a), digits(&mut b));
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                // A longer number is larger, otherwise the digits decide, then the leading zeros.
+                x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x.len().cmp(&y.len()))
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.cmp(y);
+                a.next();
+                b.next();
+                ord
+            }
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+fn digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cmp::Ordering, path::Path};
+
+    use super::{natural_cmp, SortMode};
+    use crate::{
+        config::SortConfig,
+        entry::{Entry, EntryType},
+    };
+
+    #[test]
+    fn numbers_are_compared_by_value() {
+        assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("file10", "file9"), Ordering::Greater);
+        assert_eq!(natural_cmp("v1.10.0", "v1.9.3"), Ordering::Greater);
+        assert_eq!(natural_cmp("a100b2", "a100b10"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("file02", "file3"), Ordering::Less);
+        assert_eq!(natural_cmp("file002", "file2"), Ordering::Greater);
+        assert_eq!(natural_cmp("file0", "file00"), Ordering::Less);
+    }
+
+    #[test]
+    fn everything_else_is_compared_by_character() {
+        assert_eq!(natural_cmp("abc", "abd"), Ordering::Less);
+        assert_eq!(natural_cmp("ab", "abc"), Ordering::Less);
+        assert_eq!(natural_cmp("same1", "same1"), Ordering::Equal);
+        assert_eq!(natural_cmp("", ""), Ordering::Equal);
+        assert_eq!(natural_cmp("1", "a"), Ordering::Less);
+    }
+
+    #[test]
+    fn huge_numbers_do_not_overflow() {
+        assert_eq!(
+            natural_cmp("x99999999999999999999999", "x100000000000000000000000"),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn sorting_gives_the_natural_order() {
+        let mut names = vec!["b1", "a10", "a2", "a1", "a02", "b"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["a1", "a2", "a02", "a10", "b", "b1"]);
+    }
+
+    #[test]
+    fn directories_are_sorted_by_what_is_inside_of_them() {
+        let entry = |name: &str, size, dir_size| Entry {
+            entry_type: EntryType::Dir,
+            size,
+            dir_size,
+            ..Entry::failed(Path::new(name), 1, String::new())
+        };
+        let sort = SortConfig {
+            mode: SortMode::Size,
+            ..SortConfig::default()
+        };
+        let (small, large) = (
+            entry("small", 4096, Some(10)),
+            entry("large", 0, Some(1000)),
+        );
+        assert_eq!(sort.compare(&large, &small), Ordering::Less);
+        // Until it's worked out, the directory's own size is used.
+        let unknown = entry("unknown", 4096, None);
+        assert_eq!(sort.compare(&unknown, &small), Ordering::Less);
+    }
+}
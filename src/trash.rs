@@ -0,0 +1,367 @@
+use std::{
+    ffi::OsString,
+    fs,
+    io::ErrorKind,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use ascii_forge::prelude::*;
+use percent_encoding::{percent_decode, percent_encode, AsciiSet, CONTROLS};
+
+use crate::{display, file_ops};
+
+// Characters escaped in a `.trashinfo` path, like a URL path but keeping `/`.
+const PATH_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// A trashed entry, as described by its `.trashinfo` file.
+#[derive(Clone)]
+pub struct TrashItem {
+    // The entry inside of `files`, and the info file describing it.
+    pub path: PathBuf,
+    pub info: PathBuf,
+
+    pub original: PathBuf,
+    pub deleted: String,
+}
+
+impl TrashItem {
+    /// Moves the item back to where it was trashed from, leaving its info file for the caller.
+    pub fn restore(&self) -> anyhow::Result<()> {
+        if let Some(parent) = self.original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        file_ops::move_path(&self.path, &self.original)
+    }
+}
+
+/// The user's home trash, following the freedesktop.org trash specification.
+pub struct Trash {
+    dir: PathBuf,
+}
+
+impl Trash {
+    /// Returns the trash in `$XDG_DATA_HOME/Trash`.
+    pub fn home() -> anyhow::Result<Self> {
+        let data = dirs::data_dir().ok_or(anyhow!("no data directory to keep a trash in"))?;
+        Ok(Self::at(&data))
+    }
+
+    /// Returns the trash kept in the data directory.
+    fn at(data: &Path) -> Self {
+        Self {
+            dir: data.join("Trash"),
+        }
+    }
+
+    fn files(&self) -> PathBuf {
+        self.dir.join("files")
+    }
+
+    fn info(&self) -> PathBuf {
+        self.dir.join("info")
+    }
+
+    /// Moves the path into the trash, returning the move along with the info file written for it.
+    pub fn put(&self, path: &Path) -> anyhow::Result<((PathBuf, PathBuf), PathBuf)> {
+        fs::create_dir_all(self.files())?;
+        fs::create_dir_all(self.info())?;
+
+        let name = path
+            .file_name()
+            .ok_or(anyhow!("can't trash {}", path.display()))?;
+
+        // Creating the info file first reserves the name, even with other programs trashing at once.
+        let mut n = 1;
+        let (trashed, info, mut file) = loop {
+            let candidate = match n {
+                1 => name.to_os_string(),
+                _ => numbered(name, n),
+            };
+            n += 1;
+
+            let trashed = self.files().join(&candidate);
+            if trashed.symlink_metadata().is_ok() {
+                continue;
+            }
+            let mut info_name = candidate;
+            info_name.push(".trashinfo");
+            let info = self.info().join(info_name);
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info)
+            {
+                Ok(file) => break (trashed, info, file),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let contents = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(path.as_os_str().as_bytes(), PATH_SET),
+            display::local_time(SystemTime::now()).replacen(' ', "T", 1)
+        );
+        let res = std::io::Write::write_all(&mut file, contents.as_bytes())
+            .map_err(anyhow::Error::from)
+            .and_then(|_| file_ops::move_path(path, &trashed));
+        if let Err(e) = res {
+            let _ = fs::remove_file(&info);
+            return Err(e);
+        }
+        Ok(((path.to_path_buf(), trashed), info))
+    }
+
+    /// Lists every item in the trash, most recently deleted first.
+    /// Info files that can't be read, or whose entry is gone, are skipped.
+    pub fn items(&self) -> Vec<TrashItem> {
+        let Ok(read_dir) = fs::read_dir(self.info()) else {
+            return vec![];
+        };
+
+        let mut items = read_dir
+            .flatten()
+            .filter_map(|x| self.item(&x.path()))
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| b.deleted.cmp(&a.deleted));
+        items
+    }
+
+    fn item(&self, info: &Path) -> Option<TrashItem> {
+        let name = info.file_name()?.as_bytes().strip_suffix(b".trashinfo")?;
+        let path = self.files().join(OsString::from_vec(name.to_vec()));
+        path.symlink_metadata().ok()?;
+
+        let contents = fs::read_to_string(info).ok()?;
+        let mut original = None;
+        let mut deleted = String::new();
+        for line in contents.lines() {
+            if let Some(value) = line.strip_prefix("Path=") {
+                let bytes = percent_decode(value.as_bytes()).collect::<Vec<_>>();
+                // Relative paths are relative to the directory the trash lives in.
+                original = Some(self.dir.parent()?.join(OsString::from_vec(bytes)));
+            } else if let Some(value) = line.strip_prefix("DeletionDate=") {
+                deleted = value.replace('T', " ");
+            }
+        }
+
+        Some(TrashItem {
+            path,
+            info: info.to_path_buf(),
+            original: original?,
+            deleted,
+        })
+    }
+}
+
+/// Adds a number before the extension, so `a.txt` becomes `a.2.txt`.
+fn numbered(name: &std::ffi::OsStr, n: usize) -> OsString {
+    let path = Path::new(name);
+    let mut numbered = path.file_stem().unwrap_or(name).to_os_string();
+    numbered.push(format!(".{n}"));
+    if let Some(ext) = path.extension() {
+        numbered.push(".");
+        numbered.push(ext);
+    }
+    numbered
+}
+
+pub enum TrashEvent {
+    Close,
+    Restore(TrashItem),
+}
+
+/// A list of everything in the trash, where items can be picked to be restored.
+#[derive(Default)]
+pub struct TrashView {
+    active: bool,
+    items: Vec<TrashItem>,
+    selected: usize,
+}
+
+impl TrashView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn open(&mut self) -> anyhow::Result<()> {
+        self.items = Trash::home()?.items();
+        self.selected = 0;
+        self.active = true;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn handle(&mut self, k: KeyEvent) -> Option<TrashEvent> {
+        if !(k.kind == KeyEventKind::Press || k.kind == KeyEventKind::Repeat) {
+            return None;
+        }
+
+        match k.code {
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.items.len().saturating_sub(1))
+            }
+            KeyCode::Enter | KeyCode::Char('r') => {
+                let item = self.items.get(self.selected)?.clone();
+                self.active = false;
+                return Some(TrashEvent::Restore(item));
+            }
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.active = false;
+                return Some(TrashEvent::Close);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl Render for TrashView {
+    fn render(&self, mut loc: Vec2, buffer: &mut Buffer) -> Vec2 {
+        let max_lines = (buffer.size().y as usize)
+            .saturating_sub(7)
+            .min(self.items.len());
+        let start_line = (self.selected + 1).saturating_sub(max_lines);
+
+        render!(buffer, loc => [ "Trash" ]);
+
+        for (i, item) in self
+            .items
+            .iter()
+            .enumerate()
+            .skip(start_line)
+            .take(max_lines)
+        {
+            loc.y += 1;
+
+            let original = item.original.display().to_string();
+            let deleted = format!("  {}", item.deleted);
+            if i == self.selected {
+                render!(buffer, loc => [ " > ", original.green(), deleted.dark_grey(), " <" ]);
+            } else {
+                render!(buffer, loc => [ "   ", original, deleted.dark_grey() ]);
+            }
+        }
+        loc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::OsString,
+        fs,
+        os::unix::ffi::{OsStrExt, OsStringExt},
+        path::PathBuf,
+    };
+
+    use super::Trash;
+
+    /// Deletes a test's directory once it's done, even if it failed.
+    struct Cleanup(PathBuf);
+
+    impl Drop for Cleanup {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Makes a fresh directory for the test, with a trash of its own and a directory for its files.
+    fn setup(test: &str) -> (Trash, PathBuf, Cleanup) {
+        let data = std::env::temp_dir().join(format!("idex-trash-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&data);
+        let dir = data.join("files");
+        fs::create_dir_all(&dir).unwrap();
+        (Trash::at(&data), dir, Cleanup(data))
+    }
+
+    #[test]
+    fn put_items_and_restore_round_trip() {
+        let (trash, dir, _cleanup) = setup("round_trip");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "hello").unwrap();
+
+        let ((from, trashed), info) = trash.put(&path).unwrap();
+        assert_eq!(from, path);
+        assert!(!path.exists());
+        assert!(trashed.starts_with(trash.files()));
+
+        let item = trash.items().into_iter().find(|x| x.info == info).unwrap();
+        assert_eq!(item.path, trashed);
+        assert_eq!(item.original, path);
+
+        item.restore().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+    }
+
+    #[test]
+    fn taken_names_are_numbered() {
+        let (trash, dir, _cleanup) = setup("numbered");
+        let a = dir.join("a");
+        let b = dir.join("b");
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("same.tar.gz"), "a").unwrap();
+        fs::write(b.join("same.tar.gz"), "b").unwrap();
+
+        let first = trash.put(&a.join("same.tar.gz")).unwrap().0 .1;
+        let second = trash.put(&b.join("same.tar.gz")).unwrap().0 .1;
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(second).unwrap(), "b");
+    }
+
+    #[test]
+    fn paths_are_percent_encoded() {
+        let (trash, dir, _cleanup) = setup("encoded");
+        let mut name = OsString::from("a b%#?");
+        name.push(OsString::from_vec(vec![0xff]));
+        let path = dir.join(&name);
+        fs::write(&path, "").unwrap();
+
+        let (_, info) = trash.put(&path).unwrap();
+        let contents = fs::read_to_string(&info).unwrap();
+        let line = contents.lines().find(|x| x.starts_with("Path=")).unwrap();
+        assert!(line.ends_with("/a%20b%25%23%3F%FF"), "{line}");
+        assert!(line.is_ascii());
+
+        let item = trash.items().into_iter().find(|x| x.info == info).unwrap();
+        assert_eq!(
+            item.original.as_os_str().as_bytes(),
+            path.as_os_str().as_bytes()
+        );
+    }
+
+    #[test]
+    fn items_without_an_entry_are_skipped() {
+        let (trash, dir, _cleanup) = setup("skipped");
+        let path = dir.join("gone");
+        fs::write(&path, "").unwrap();
+
+        let ((_, trashed), info) = trash.put(&path).unwrap();
+        fs::remove_file(trashed).unwrap();
+        assert!(trash.items().iter().all(|x| x.info != info));
+    }
+}
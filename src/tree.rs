@@ -0,0 +1,420 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    ops::{Index, IndexMut},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, TryRecvError},
+};
+
+use crate::{config::SortConfig, dir_items::Listing, entry::Entry};
+
+/// The entries of the explorer, stored as a tree of the directories that have been read.
+/// The tree is kept flattened into the order it is shown in, so entries can be looked up by their
+/// row or by their path without walking it. When a directory's children change, only its own rows
+/// are flattened again and spliced in, and rows are only looked up by path again once they moved.
+pub struct Tree {
+    root: PathBuf,
+    // Every entry that has been read, by its path.
+    entries: HashMap<PathBuf, Entry>,
+    // The sorted children of the root and of every expanded directory.
+    children: HashMap<PathBuf, Vec<PathBuf>>,
+
+    // The order entries are shown in, and how many rows are shown inside of each directory.
+    flat: Vec<PathBuf>,
+    sizes: HashMap<PathBuf, usize>,
+    // Where entries were last found in `flat`, which is up to date for every row before `indexed`.
+    // Rows after it may have moved, and are indexed again as they are looked for.
+    index: RefCell<HashMap<PathBuf, usize>>,
+    indexed: Cell<usize>,
+
+    // Directories that are still being read in the background.
+    streams: Vec<Stream>,
+}
+
+/// A directory that is still being read in the background.
+struct Stream {
+    dir: PathBuf,
+    receiver: Receiver<Vec<Entry>>,
+    // For a refresh, every child read so far, so that those that are gone can be removed once it is done.
+    seen: Option<HashSet<PathBuf>>,
+}
+
+impl Tree {
+    pub fn new(root: &Path, listing: Listing) -> Self {
+        let mut tree = Self {
+            root: root.to_path_buf(),
+            entries: HashMap::new(),
+            children: HashMap::new(),
+            flat: vec![],
+            sizes: HashMap::new(),
+            index: RefCell::new(HashMap::new()),
+            indexed: Cell::new(0),
+            streams: vec![],
+        };
+        tree.set_children(root, listing);
+        tree
+    }
+
+    /// Returns the number of entries shown.
+    pub fn len(&self) -> usize {
+        self.flat.len()
+    }
+
+    /// Returns the entry shown at `idx`.
+    pub fn get(&self, idx: usize) -> Option<&Entry> {
+        self.flat.get(idx).map(|x| &self.entries[x])
+    }
+
+    /// Returns the entries in the order they are shown.
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.flat.iter().map(|x| &self.entries[x])
+    }
+
+    /// Returns where the entry at `path` is shown.
+    pub fn find(&self, path: &Path) -> Option<usize> {
+        let mut index = self.index.borrow_mut();
+        // Paths are only shown once, so the row is still right if the path is still there.
+        if let Some(&idx) = index.get(path) {
+            if self.flat.get(idx).is_some_and(|x| x == path) {
+                return Some(idx);
+            }
+        }
+        // Otherwise it moved, so the rows that may have moved are indexed until it turns up.
+        while let Some(row) = self.flat.get(self.indexed.get()) {
+            let idx = self.indexed.get();
+            self.indexed.set(idx + 1);
+            index.insert(row.clone(), idx);
+            if row == path {
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+    pub fn entry_mut(&mut self, path: &Path) -> Option<&mut Entry> {
+        self.entries.get_mut(path)
+    }
+
+    /// Returns the paths of the directory's children, if it has been read.
+    pub fn children(&self, dir: &Path) -> &[PathBuf] {
+        self.children.get(dir).map_or(&[], Vec::as_slice)
+    }
+
+    /// Shows the entries of a directory that has just been expanded.
+    pub fn set_children(&mut self, dir: &Path, listing: Listing) {
+        self.streams.retain(|x| x.dir != dir);
+        let mut children = vec![];
+        for entry in listing.entries {
+            children.push(entry.path.clone());
+            self.entries.insert(entry.path.clone(), entry);
+        }
+        self.children.insert(dir.to_path_buf(), children);
+        if let Some(receiver) = listing.rest {
+            self.streams.push(Stream {
+                dir: dir.to_path_buf(),
+                receiver,
+                seen: None,
+            });
+        }
+        self.reflow(dir);
+    }
+
+    /// Hides everything inside of a directory that has been collapsed, returning what was removed.
+    pub fn remove_children(&mut self, dir: &Path) -> Vec<Entry> {
+        let mut removed = vec![];
+        for child in self.children.remove(dir).unwrap_or_default() {
+            self.remove_subtree(&child, &mut removed);
+        }
+        self.streams.retain(|x| x.dir != dir);
+        self.reflow(dir);
+        removed
+    }
+
+    /// Replaces a directory's children with a fresh read of them, returning what was removed.
+    /// Children that still exist keep their expanded subtrees. While the rest of a large directory
+    /// is still being read, its other children are kept, until it is known which of them are gone.
+    pub fn replace_children(
+        &mut self,
+        dir: &Path,
+        listing: Listing,
+        sort: &SortConfig,
+    ) -> Vec<Entry> {
+        // What was still coming in from an earlier read is out of date.
+        self.streams.retain(|x| x.dir != dir);
+
+        let old = self.children.remove(dir).unwrap_or_default();
+        let mut removed = vec![];
+        let mut children = vec![];
+        for entry in listing.entries {
+            children.push(self.merge(entry, &mut removed));
+        }
+        let seen = children.iter().cloned().collect::<HashSet<_>>();
+        let unseen = old
+            .into_iter()
+            .filter(|x| !seen.contains(x))
+            .collect::<Vec<_>>();
+
+        match listing.rest {
+            Some(receiver) => {
+                children.extend(unseen);
+                children.sort_by(|a, b| sort.compare(&self.entries[a], &self.entries[b]));
+                self.streams.push(Stream {
+                    dir: dir.to_path_buf(),
+                    receiver,
+                    seen: Some(seen),
+                });
+            }
+            None => {
+                for path in unseen {
+                    self.remove_subtree(&path, &mut removed);
+                }
+            }
+        }
+        self.children.insert(dir.to_path_buf(), children);
+        self.reflow(dir);
+        removed
+    }
+
+    /// Stores an entry that has been read, returning its path.
+    /// An entry that was already there keeps its subtree, unless it changed type.
+    fn merge(&mut self, entry: Entry, removed: &mut Vec<Entry>) -> PathBuf {
+        let path = entry.path.clone();
+        match self.entries.remove(&path) {
+            Some(old) if old.entry_type == entry.entry_type && old.is_dir() == entry.is_dir() => {
+                // Keep the old entry's subtree, but with its new metadata.
+                self.entries.insert(
+                    path.clone(),
+                    Entry {
+                        expanded: old.expanded,
+                        dir_size: old.dir_size,
+                        ..entry
+                    },
+                );
+            }
+            Some(old) => {
+                self.entries.insert(path.clone(), old);
+                self.remove_subtree(&path, removed);
+                self.entries.insert(path.clone(), entry);
+            }
+            None => {
+                self.entries.insert(path.clone(), entry);
+            }
+        }
+        path
+    }
+
+    /// Sorts every directory that has been read again.
+    pub fn resort(&mut self, sort: &SortConfig) {
+        for children in self.children.values_mut() {
+            children.sort_by(|a, b| sort.compare(&self.entries[a], &self.entries[b]));
+        }
+        let root = self.root.clone();
+        self.reflow(&root);
+    }
+
+    /// Adds whatever has been read in the background since the last update.
+    /// Returns what was removed if anything changed, which is only ever something a refresh found gone.
+    pub fn update(&mut self, sort: &SortConfig) -> Option<Vec<Entry>> {
+        // The directories that changed, each only flattened again once however many batches came in.
+        let mut changed = vec![];
+        let mut removed = vec![];
+        for mut stream in std::mem::take(&mut self.streams) {
+            let mut done = false;
+            loop {
+                match stream.receiver.try_recv() {
+                    Ok(batch) => {
+                        self.add_children(&mut stream, batch, sort, &mut removed);
+                        if !changed.contains(&stream.dir) {
+                            changed.push(stream.dir.clone());
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        done = true;
+                        break;
+                    }
+                }
+            }
+            if !done {
+                self.streams.push(stream);
+            } else if let Some(seen) = stream.seen {
+                // The refresh has read the whole directory, so whatever it didn't see is gone.
+                let gone = self
+                    .children(&stream.dir)
+                    .iter()
+                    .filter(|x| !seen.contains(*x))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if let Some(children) = self.children.get_mut(&stream.dir) {
+                    children.retain(|x| seen.contains(x));
+                }
+                for path in gone {
+                    self.remove_subtree(&path, &mut removed);
+                }
+                if !changed.contains(&stream.dir) {
+                    changed.push(stream.dir);
+                }
+            }
+        }
+        for dir in &changed {
+            self.reflow(dir);
+        }
+        (!changed.is_empty()).then_some(removed)
+    }
+
+    /// Returns whether any directory is still being read.
+    pub fn loading(&self) -> bool {
+        !self.streams.is_empty()
+    }
+
+    fn add_children(
+        &mut self,
+        stream: &mut Stream,
+        entries: Vec<Entry>,
+        sort: &SortConfig,
+        removed: &mut Vec<Entry>,
+    ) {
+        if !self.children.contains_key(&stream.dir) {
+            return;
+        }
+        let mut added = vec![];
+        for entry in entries {
+            if let Some(seen) = &mut stream.seen {
+                seen.insert(entry.path.clone());
+            }
+            // Entries that are already children, like those a refresh kept, are only updated.
+            let new = !self.entries.contains_key(&entry.path);
+            let path = self.merge(entry, removed);
+            if new {
+                added.push(path);
+            }
+        }
+        if let Some(children) = self.children.get_mut(&stream.dir) {
+            children.extend(added);
+            // Both the children and the batch are already sorted, which the sort only has to merge.
+            children.sort_by(|a, b| sort.compare(&self.entries[a], &self.entries[b]));
+        }
+    }
+
+    fn remove_subtree(&mut self, path: &Path, removed: &mut Vec<Entry>) {
+        for child in self.children.remove(path).unwrap_or_default() {
+            self.remove_subtree(&child, removed);
+        }
+        self.streams.retain(|x| x.dir != path);
+        self.sizes.remove(path);
+        self.index.get_mut().remove(path);
+        removed.extend(self.entries.remove(path));
+    }
+
+    /// Flattens the rows inside of a directory whose children changed again, and splices them in
+    /// over the ones it had before. Only the directory's own rows are walked, besides moving the rest.
+    fn reflow(&mut self, dir: &Path) {
+        let start = match dir == self.root {
+            true => 0,
+            false => match self.find(dir) {
+                Some(idx) => idx + 1,
+                // Nothing inside of a directory that isn't shown is shown either.
+                None => return,
+            },
+        };
+        let old = self.sizes.get(dir).copied().unwrap_or(0);
+        let mut rows = vec![];
+        flatten(&self.children, &mut self.sizes, dir, &mut rows);
+        let new = rows.len();
+        self.flat.splice(start..start + old, rows);
+
+        // Everything after the directory's rows moved, so it has to be indexed again.
+        self.indexed.set(self.indexed.get().min(start));
+        for ancestor in dir.ancestors().skip(1) {
+            if let Some(size) = self.sizes.get_mut(ancestor) {
+                *size = *size + new - old;
+            }
+            if ancestor == self.root {
+                break;
+            }
+        }
+    }
+}
+
+/// Adds the rows shown inside of a directory to `rows`, with each directory's children right after it,
+/// storing how many rows there are inside of every directory on the way.
+fn flatten(
+    children: &HashMap<PathBuf, Vec<PathBuf>>,
+    sizes: &mut HashMap<PathBuf, usize>,
+    dir: &Path,
+    rows: &mut Vec<PathBuf>,
+) {
+    let Some(dir_children) = children.get(dir) else {
+        sizes.remove(dir);
+        return;
+    };
+    let start = rows.len();
+    for child in dir_children {
+        rows.push(child.clone());
+        flatten(children, sizes, child, rows);
+    }
+    sizes.insert(dir.to_path_buf(), rows.len() - start);
+}
+
+impl Index<usize> for Tree {
+    type Output = Entry;
+
+    fn index(&self, idx: usize) -> &Entry {
+        self.entries
+            .get(&self.flat[idx])
+            .expect("Shown entries should be in the tree")
+    }
+}
+
+impl IndexMut<usize> for Tree {
+    fn index_mut(&mut self, idx: usize) -> &mut Entry {
+        self.entries
+            .get_mut(&self.flat[idx])
+            .expect("Shown entries should be in the tree")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::Tree;
+    use crate::{config::SortConfig, dir_items::Listing, entry::Entry};
+
+    fn listing(paths: &[&str]) -> Listing {
+        Listing {
+            entries: paths
+                .iter()
+                .map(|x| Entry::failed(Path::new(x), 1, String::new()))
+                .collect(),
+            rest: None,
+        }
+    }
+
+    fn rows(tree: &Tree) -> Vec<PathBuf> {
+        tree.iter().map(|x| x.path.clone()).collect()
+    }
+
+    #[test]
+    fn changed_directories_are_spliced_in() {
+        let mut tree = Tree::new(Path::new("/r"), listing(&["/r/a", "/r/b", "/r/c"]));
+        tree.set_children(Path::new("/r/c"), listing(&["/r/c/z"]));
+        tree.set_children(Path::new("/r/a"), listing(&["/r/a/x", "/r/a/y"]));
+        tree.set_children(Path::new("/r/a/x"), listing(&["/r/a/x/1"]));
+        assert_eq!(tree.find(Path::new("/r/c/z")), Some(6));
+        assert_eq!(tree.find(Path::new("/r/a/x/1")), Some(2));
+
+        tree.remove_children(Path::new("/r/a/x"));
+        assert_eq!(tree.find(Path::new("/r/c")), Some(4));
+        assert_eq!(tree.find(Path::new("/r/a/x/1")), None);
+
+        let sort = SortConfig::default();
+        tree.replace_children(Path::new("/r/a"), listing(&["/r/a/w", "/r/a/x"]), &sort);
+        let expected = ["/r/a", "/r/a/w", "/r/a/x", "/r/b", "/r/c", "/r/c/z"];
+        assert_eq!(rows(&tree), expected.map(PathBuf::from));
+        for (i, path) in expected.iter().enumerate() {
+            assert_eq!(tree.find(Path::new(path)), Some(i));
+        }
+        assert_eq!(tree.find(Path::new("/r/a/y")), None);
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        OnceLock,
+    },
+    thread,
+};
+
+use anyhow::anyhow;
+use ascii_forge::prelude::*;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{FontStyle, Theme},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+use two_face::theme::LazyThemeSet;
+
+use crate::{
+    config::{Config, ViewerConfig},
+    output,
+};
+
+// How many highlighted lines are sent back at once.
+const BATCH_SIZE: usize = 256;
+
+// Loading the grammars and themes takes a moment, so it is only done once the viewer is first opened.
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEMES: OnceLock<LazyThemeSet> = OnceLock::new();
+
+fn syntaxes() -> &'static SyntaxSet {
+    SYNTAXES.get_or_init(two_face::syntax::extra_newlines)
+}
+
+fn themes() -> &'static LazyThemeSet {
+    THEMES.get_or_init(|| LazyThemeSet::from(two_face::theme::extra()))
+}
+
+type Line = Vec<StyledContent<String>>;
+
+pub enum ViewerEvent {
+    Close,
+}
+
+/// A read-only view of a file, highlighted by its extension.
+/// Files are highlighted in the background, and lines are shown as soon as they are ready.
+pub struct Viewer {
+    active: bool,
+    config: ViewerConfig,
+
+    path: PathBuf,
+    syntax: String,
+    lines: Vec<Line>,
+    truncated: bool,
+
+    scroll: usize,
+    // How many lines were visible the last time the viewer was rendered, for paging.
+    height: Cell<usize>,
+
+    receiver: Option<Receiver<Vec<Line>>>,
+}
+
+impl Viewer {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            active: false,
+            config: config.viewer.clone(),
+            path: PathBuf::new(),
+            syntax: String::new(),
+            lines: vec![],
+            truncated: false,
+            scroll: 0,
+            height: Cell::new(1),
+            receiver: None,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Opens the file and starts highlighting it with the configured theme.
+    pub fn open(&mut self, path: &Path) -> anyhow::Result<()> {
+        if fs::metadata(path)?.is_dir() {
+            return Err(anyhow!("{} is a directory", path.display()));
+        }
+
+        let mut bytes = vec![];
+        File::open(path)?
+            .take(self.config.max_bytes)
+            .read_to_end(&mut bytes)?;
+        if bytes.contains(&0) {
+            return Err(anyhow!("{} is a binary file", path.display()));
+        }
+
+        let theme = themes()
+            .get(&self.config.theme)
+            .ok_or(anyhow!("there is no theme named {}", self.config.theme))?
+            .clone();
+
+        // Syntaxes are found by extension, then by the first line, like a `#!` or a modeline.
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        let syntaxes = syntaxes();
+        let syntax = syntaxes
+            .find_syntax_for_file(path)
+            .ok()
+            .flatten()
+            .or_else(|| syntaxes.find_syntax_by_first_line(&text))
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+
+        let (sender, receiver) = channel();
+        highlight(text, syntax.name.clone(), theme, sender);
+
+        self.path = path.to_path_buf();
+        self.syntax = syntax.name.clone();
+        self.lines.clear();
+        self.truncated = fs::metadata(path)?.len() > bytes.len() as u64;
+        self.scroll = 0;
+        self.receiver = Some(receiver);
+        self.active = true;
+        Ok(())
+    }
+
+    /// Collects any lines that have been highlighted since the last update.
+    pub fn update(&mut self) {
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+        for lines in receiver.try_iter() {
+            self.lines.extend(lines);
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn syntax(&self) -> &str {
+        &self.syntax
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns how far through the file the bottom of the view is, as a percentage.
+    pub fn percent(&self) -> usize {
+        let bottom = (self.scroll + self.height.get()).min(self.lines.len());
+        bottom * 100 / self.lines.len().max(1)
+    }
+
+    fn scroll_by(&mut self, dist: isize) {
+        let max = self.lines.len().saturating_sub(self.height.get());
+        self.scroll = self.scroll.saturating_add_signed(dist).min(max);
+    }
+
+    pub fn handle(&mut self, k: KeyEvent) -> Option<ViewerEvent> {
+        if !(k.kind == KeyEventKind::Press || k.kind == KeyEventKind::Repeat) {
+            return None;
+        }
+
+        let page = self.height.get() as isize;
+        match k.code {
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_by(1),
+            KeyCode::PageUp => self.scroll_by(-page),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.scroll_by(page),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll = 0,
+            KeyCode::End | KeyCode::Char('G') => self.scroll_by(isize::MAX),
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.active = false;
+                self.lines.clear();
+                self.receiver = None;
+                return Some(ViewerEvent::Close);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl Render for Viewer {
+    fn render(&self, loc: Vec2, buffer: &mut Buffer) -> Vec2 {
+        let size = buffer.size();
+        let height = size.y.saturating_sub(loc.y + 6) as usize;
+        self.height.set(height.max(1));
+
+        let digits = self.lines.len().max(1).ilog10() as usize + 1;
+        for (i, line) in self.lines.iter().enumerate().skip(self.scroll).take(height) {
+            let mut spans = vec![format!("{:>digits$} ", i + 1).dark_grey()];
+            spans.extend(line.iter().cloned());
+            let loc = vec2(loc.x, loc.y + (i - self.scroll) as u16);
+            output::render_clipped(&spans, loc, size.x.saturating_sub(loc.x), buffer);
+        }
+        vec2(loc.x, loc.y + height as u16)
+    }
+}
+
+/// Highlights the text line by line on another thread, sending the lines back in batches.
+fn highlight(text: String, syntax: String, theme: Theme, sender: Sender<Vec<Line>>) {
+    thread::spawn(move || {
+        let syntaxes = syntaxes();
+        let Some(syntax) = syntaxes.find_syntax_by_name(&syntax) else {
+            return;
+        };
+        let mut highlighter = HighlightLines::new(syntax, &theme);
+
+        let mut batch = vec![];
+        for line in LinesWithEndings::from(&text) {
+            let spans = match highlighter.highlight_line(line, syntaxes) {
+                Ok(ranges) => ranges
+                    .into_iter()
+                    .flat_map(|(style, text)| output::spans(text, content_style(style)))
+                    .collect(),
+                Err(_) => output::spans(line, ContentStyle::new()),
+            };
+            batch.push(spans);
+
+            if batch.len() >= BATCH_SIZE && sender.send(std::mem::take(&mut batch)).is_err() {
+                // The viewer was closed, so there is no one left to highlight for.
+                return;
+            }
+        }
+        let _ = sender.send(batch);
+    });
+}
+
+/// Converts a highlighting style to a terminal one, leaving the background to the terminal.
+fn content_style(style: syntect::highlighting::Style) -> ContentStyle {
+    let mut content = ContentStyle::new();
+    let color = style.foreground;
+    // Like bat's `ansi` and `base16` themes, an alpha of 0 means `r` is a terminal color,
+    // and an alpha of 1 means the terminal's own foreground.
+    content.foreground_color = match color.a {
+        0 => Some(Color::AnsiValue(color.r)),
+        1 => None,
+        _ => Some(Color::Rgb {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+        }),
+    };
+    if style.font_style.contains(FontStyle::BOLD) {
+        content.attributes.set(Attribute::Bold);
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        content.attributes.set(Attribute::Italic);
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        content.attributes.set(Attribute::Underlined);
+    }
+    content
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
+
+use notify::{EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How often the fallback watcher rescans the watched directories.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+type WatchEvent = notify::Result<notify::Event>;
+
+/// Watches the expanded directories of the explorer for changes.
+/// Uses the native watcher (inotify on linux), falling back to polling if it isn't available.
+pub struct DirWatcher {
+    watcher: Box<dyn Watcher>,
+    polling: bool,
+    sender: Sender<WatchEvent>,
+    receiver: Receiver<WatchEvent>,
+    watched: HashSet<PathBuf>,
+}
+
+impl DirWatcher {
+    pub fn new() -> anyhow::Result<Self> {
+        let (sender, receiver) = channel();
+        let (watcher, polling): (Box<dyn Watcher>, bool) =
+            match RecommendedWatcher::new(sender.clone(), notify::Config::default()) {
+                Ok(w) => (Box::new(w), false),
+                Err(_) => (Box::new(Self::poll_watcher(sender.clone())?), true),
+            };
+
+        Ok(Self {
+            watcher,
+            polling,
+            sender,
+            receiver,
+            watched: HashSet::new(),
+        })
+    }
+
+    fn poll_watcher(sender: Sender<WatchEvent>) -> notify::Result<PollWatcher> {
+        PollWatcher::new(
+            sender,
+            notify::Config::default().with_poll_interval(POLL_INTERVAL),
+        )
+    }
+
+    /// Replaces the native watcher with a polling one, re-watching every directory.
+    fn fall_back_to_polling(&mut self) -> anyhow::Result<()> {
+        self.watcher = Box::new(Self::poll_watcher(self.sender.clone())?);
+        self.polling = true;
+        for path in &self.watched {
+            self.watcher.watch(path, RecursiveMode::NonRecursive)?;
+        }
+        Ok(())
+    }
+
+    pub fn watch(&mut self, path: &Path) -> anyhow::Result<()> {
+        if !self.watched.insert(path.to_path_buf()) {
+            return Ok(());
+        }
+        if let Err(e) = self.watcher.watch(path, RecursiveMode::NonRecursive) {
+            // Running out of inotify watches is the most common failure, so polling is the safe option.
+            if self.polling {
+                return Err(e.into());
+            }
+            self.fall_back_to_polling()?;
+        }
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        if self.watched.remove(path) {
+            // The directory may already be gone, in which case it's no longer watched anyways.
+            let _ = self.watcher.unwatch(path);
+        }
+    }
+
+    /// Drains the pending events, returning every watched directory whose contents changed.
+    pub fn changed_dirs(&mut self) -> HashSet<PathBuf> {
+        let mut dirs = HashSet::new();
+        for event in self.receiver.try_iter().flatten() {
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            for path in event.paths {
+                if self.watched.contains(&path) {
+                    dirs.insert(path.clone());
+                }
+                if let Some(parent) = path.parent() {
+                    if self.watched.contains(parent) {
+                        dirs.insert(parent.to_path_buf());
+                    }
+                }
+            }
+        }
+        dirs
+    }
+}